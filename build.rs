@@ -4,6 +4,7 @@ const COMMANDS: &[&str] = &[
     "validate_key",
     "activate",
     "checkout_machine",
+    "update_machine",
     "reset_license",
     "reset_license_key",
 ];
@@ -1,6 +1,7 @@
 'use strict';
 
 var core = require('@tauri-apps/api/core');
+var event = require('@tauri-apps/api/event');
 
 function isErrorWithMessage(error) {
     return (typeof error === "object" &&
@@ -111,6 +112,14 @@ async function validateCheckoutKey({ key, entitlements = [], ttlSeconds = 86400,
         throwError(e);
     }
 }
+async function updateMachine() {
+    try {
+        return await core.invoke("plugin:keygen|update_machine");
+    }
+    catch (e) {
+        throwError(e);
+    }
+}
 async function resetLicense() {
     try {
         return await core.invoke("plugin:keygen|reset_license");
@@ -132,11 +141,35 @@ async function resetLicenseKey() {
         throwError(e);
     }
 }
+async function onLicenseChanged(handler) {
+    return await event.listen("keygen://license-changed", (e) => handler(e.payload));
+}
+/**
+ * A valid license within 72 hours of its expiry,
+ * or running on the offline grace period.
+ */
+async function onExpiring(handler) {
+    return await event.listen("keygen://expiring", (e) => handler(e.payload));
+}
+/**
+ * Keygen couldn't be reached. Receives the license the app is still running on.
+ */
+async function onOffline(handler) {
+    return await event.listen("keygen://offline", (e) => handler(e.payload));
+}
+async function onError(handler) {
+    return await event.listen("keygen://error", (e) => handler(new KeygenError(e.payload)));
+}
 
 exports.KeygenError = KeygenError;
 exports.getLicense = getLicense;
 exports.getLicenseKey = getLicenseKey;
+exports.onError = onError;
+exports.onExpiring = onExpiring;
+exports.onLicenseChanged = onLicenseChanged;
+exports.onOffline = onOffline;
 exports.resetLicense = resetLicense;
 exports.resetLicenseKey = resetLicenseKey;
+exports.updateMachine = updateMachine;
 exports.validateCheckoutKey = validateCheckoutKey;
 exports.validateKey = validateKey;
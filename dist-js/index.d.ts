@@ -1,3 +1,5 @@
+import { type UnlistenFn } from "@tauri-apps/api/event";
+import { KeygenError } from "./error";
 export declare type KeygenLicense = {
     key: string;
     code: string;
@@ -11,6 +13,11 @@ export declare type KeygenLicense = {
     policyId: string;
     entitlements: string[];
     metadata: Record<string, any>;
+    /**
+     * Seconds left of the offline grace period since the last successful validation.
+     * Null when the grace period is disabled, or for machine file licenses.
+     */
+    graceRemaining: number | null;
 };
 export { KeygenError } from "./error";
 export declare function getLicense(): Promise<KeygenLicense | null>;
@@ -26,5 +33,17 @@ export declare function validateCheckoutKey({ key, entitlements, ttlSeconds, ttl
     ttlSeconds?: number;
     ttlForever?: boolean;
 }): Promise<KeygenLicense>;
+export declare function updateMachine(): Promise<void>;
 export declare function resetLicense(): Promise<void>;
 export declare function resetLicenseKey(): Promise<void>;
+export declare function onLicenseChanged(handler: (license: KeygenLicense | null) => void): Promise<UnlistenFn>;
+/**
+ * A valid license within 72 hours of its expiry,
+ * or running on the offline grace period.
+ */
+export declare function onExpiring(handler: (license: KeygenLicense) => void): Promise<UnlistenFn>;
+/**
+ * Keygen couldn't be reached. Receives the license the app is still running on.
+ */
+export declare function onOffline(handler: (license: KeygenLicense | null) => void): Promise<UnlistenFn>;
+export declare function onError(handler: (error: KeygenError) => void): Promise<UnlistenFn>;
@@ -1,4 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
 function isErrorWithMessage(error) {
     return (typeof error === "object" &&
@@ -109,6 +110,14 @@ async function validateCheckoutKey({ key, entitlements = [], ttlSeconds = 86400,
         throwError(e);
     }
 }
+async function updateMachine() {
+    try {
+        return await invoke("plugin:keygen|update_machine");
+    }
+    catch (e) {
+        throwError(e);
+    }
+}
 async function resetLicense() {
     try {
         return await invoke("plugin:keygen|reset_license");
@@ -130,5 +139,24 @@ async function resetLicenseKey() {
         throwError(e);
     }
 }
+async function onLicenseChanged(handler) {
+    return await listen("keygen://license-changed", (e) => handler(e.payload));
+}
+/**
+ * A valid license within 72 hours of its expiry,
+ * or running on the offline grace period.
+ */
+async function onExpiring(handler) {
+    return await listen("keygen://expiring", (e) => handler(e.payload));
+}
+/**
+ * Keygen couldn't be reached. Receives the license the app is still running on.
+ */
+async function onOffline(handler) {
+    return await listen("keygen://offline", (e) => handler(e.payload));
+}
+async function onError(handler) {
+    return await listen("keygen://error", (e) => handler(new KeygenError(e.payload)));
+}
 
-export { KeygenError, getLicense, getLicenseKey, resetLicense, resetLicenseKey, validateCheckoutKey, validateKey };
+export { KeygenError, getLicense, getLicenseKey, onError, onExpiring, onLicenseChanged, onOffline, resetLicense, resetLicenseKey, updateMachine, validateCheckoutKey, validateKey };
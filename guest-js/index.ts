@@ -1,4 +1,5 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 import { isKeygenError, getErrorMessage, KeygenError } from "./error";
 
 export type KeygenLicense = {
@@ -14,6 +15,11 @@ export type KeygenLicense = {
   policyId: string;
   entitlements: string[];
   metadata: Record<string, any>;
+  /**
+   * Seconds left of the offline grace period since the last successful validation.
+   * Null when the grace period is disabled, or for machine file licenses.
+   */
+  graceRemaining: number | null;
 };
 
 export { KeygenError } from "./error";
@@ -112,6 +118,14 @@ export async function validateCheckoutKey({
   }
 }
 
+export async function updateMachine(): Promise<void> {
+  try {
+    return await invoke("plugin:keygen|update_machine");
+  } catch (e) {
+    throwError(e);
+  }
+}
+
 export async function resetLicense(): Promise<void> {
   try {
     return await invoke("plugin:keygen|reset_license");
@@ -131,3 +145,42 @@ export async function resetLicenseKey(): Promise<void> {
     throwError(e);
   }
 }
+
+export async function onLicenseChanged(
+  handler: (license: KeygenLicense | null) => void
+): Promise<UnlistenFn> {
+  return await listen<KeygenLicense | null>("keygen://license-changed", (e) =>
+    handler(e.payload)
+  );
+}
+
+/**
+ * A valid license within 72 hours of its expiry,
+ * or running on the offline grace period.
+ */
+export async function onExpiring(
+  handler: (license: KeygenLicense) => void
+): Promise<UnlistenFn> {
+  return await listen<KeygenLicense>("keygen://expiring", (e) =>
+    handler(e.payload)
+  );
+}
+
+/**
+ * Keygen couldn't be reached. Receives the license the app is still running on.
+ */
+export async function onOffline(
+  handler: (license: KeygenLicense | null) => void
+): Promise<UnlistenFn> {
+  return await listen<KeygenLicense | null>("keygen://offline", (e) =>
+    handler(e.payload)
+  );
+}
+
+export async function onError(
+  handler: (error: KeygenError) => void
+): Promise<UnlistenFn> {
+  return await listen<{ code: string; detail: string }>("keygen://error", (e) =>
+    handler(new KeygenError(e.payload))
+  );
+}
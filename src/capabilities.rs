@@ -0,0 +1,139 @@
+use crate::licensed::license::License;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Mutex, PoisonError},
+};
+use tauri::{utils::acl::capability::CapabilityFile, AppHandle, Manager, Runtime};
+
+// `plugins.keygen` in tauri.conf.json
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    capabilities: CapabilitiesConfig,
+}
+
+// capability objects, by entitlement code and by policy id
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CapabilitiesConfig {
+    entitlements: BTreeMap<String, serde_json::Value>,
+    policies: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tier {
+    Entitlement(String),
+    Policy(String),
+}
+
+// a capability (json or toml, same as the files in `src-tauri/capabilities`)
+// granted at runtime to licenses of a tier
+#[derive(Debug, Clone)]
+pub struct LicenseCapability {
+    tier: Tier,
+    capability: String,
+}
+
+impl LicenseCapability {
+    pub(crate) fn entitlement(code: String, capability: String) -> Self {
+        Self {
+            tier: Tier::Entitlement(code),
+            capability,
+        }
+    }
+
+    pub(crate) fn policy(policy_id: String, capability: String) -> Self {
+        Self {
+            tier: Tier::Policy(policy_id),
+            capability,
+        }
+    }
+
+    fn unlocked_by(&self, license: &License) -> bool {
+        match &self.tier {
+            Tier::Entitlement(code) => license.has_entitlement(code),
+            Tier::Policy(policy_id) => license.valid && &license.policy_id == policy_id,
+        }
+    }
+}
+
+pub(crate) struct Capabilities {
+    capabilities: Vec<LicenseCapability>,
+    granted: Mutex<HashSet<usize>>,
+}
+
+impl Capabilities {
+    // the config's mapping, with the Builder's capabilities overriding
+    // the ones configured for the same entitlement or policy
+    pub(crate) fn new(config: Option<&Config>, overrides: Vec<LicenseCapability>) -> Self {
+        let config = config.map(|config| &config.capabilities);
+
+        let entitlements = config
+            .into_iter()
+            .flat_map(|config| &config.entitlements)
+            .map(|(code, capability)| {
+                LicenseCapability::entitlement(code.clone(), capability.to_string())
+            });
+
+        let policies = config.into_iter().flat_map(|config| &config.policies).map(
+            |(policy_id, capability)| {
+                LicenseCapability::policy(policy_id.clone(), capability.to_string())
+            },
+        );
+
+        let mut capabilities: Vec<LicenseCapability> = entitlements
+            .chain(policies)
+            .filter(|capability| {
+                !overrides
+                    .iter()
+                    .any(|override_capability| override_capability.tier == capability.tier)
+            })
+            .collect();
+
+        capabilities.extend(overrides);
+
+        Self {
+            capabilities,
+            granted: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+// grant the capabilities unlocked by the license.
+// tauri can't remove capabilities at runtime: they're kept until the app restarts.
+pub(crate) fn grant<R: Runtime>(app: &AppHandle<R>, license: Option<&License>) {
+    let Some(license) = license.filter(|license| license.valid) else {
+        return;
+    };
+
+    let Some(capabilities) = app.try_state::<Capabilities>() else {
+        return;
+    };
+
+    let mut granted = capabilities
+        .granted
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    for (i, capability) in capabilities.capabilities.iter().enumerate() {
+        if granted.contains(&i) || !capability.unlocked_by(license) {
+            continue;
+        }
+
+        // add_capability() panics on a malformed capability
+        if let Err(err) = capability.capability.parse::<CapabilityFile>() {
+            dbg!(err);
+            continue;
+        }
+
+        match app.add_capability(capability.capability.as_str()) {
+            Ok(()) => {
+                granted.insert(i);
+            }
+            Err(err) => {
+                dbg!(err);
+            }
+        }
+    }
+}
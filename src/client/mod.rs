@@ -1,30 +1,49 @@
+pub mod pinning;
 pub mod sig;
+pub mod verify_key;
 
-use crate::{err::Error, licensed::types::LicenseResponse, Result};
+use crate::{
+    err::{parse_err_json, Error},
+    licensed::types::LicenseResponse,
+    storage::{KeygenStorage, Storage, StorageEntry},
+    Result,
+};
 use base64::Engine;
 use chrono::{DateTime, Utc};
-use ed25519_dalek::{Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
-use hex::FromHex;
 use reqwest::{
     header::{HeaderMap, HeaderValue, USER_AGENT},
-    Method, RequestBuilder, Response, Url,
+    Method, RequestBuilder, Response, StatusCode, Url,
 };
 use serde::{Deserialize, Serialize};
-use sig::KeygenSig;
+use sig::{KeygenSig, SignatureAlgorithm};
+use std::sync::atomic::{AtomicI64, Ordering};
+use verify_key::{ParsedVerifyKey, VerifyKey};
+
+// persist the skew or the last seen date only when they moved by more than this
+// (in seconds), not on every request's latency jitter
+static CLOCK_PERSIST_THRESHOLD: i64 = 30;
+
+// how far (in seconds) the corrected clock may fall behind the last seen date
+// before it counts as set back, e.g. after an NTP adjustment
+static CLOCK_SET_BACK_TOLERANCE: i64 = 5 * 60;
 
-#[derive(Debug)]
 pub struct KeygenClient {
     custom_domain: Option<String>,
     account_id: Option<String>,
-    verify_key: String,
+    verify_keys: Vec<ParsedVerifyKey>,
+    signature_algorithm: SignatureAlgorithm,
     api_url: Option<String>,
     api_version: u8, // Keygen API major version
     http_client: reqwest::Client,
-    max_clock_drift: i64, // in minutes
-    cache_lifetime: i64,  // in minutes
+    max_clock_drift: i64,  // in minutes
+    clock_skew: AtomicI64, // in seconds: server time - local time
+    last_seen: AtomicI64,  // newest signed server date, as a unix timestamp
+    storage: Storage,
+    cache_lifetime: i64, // in minutes
+    grace_period: i64,   // in days
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KeygenResponseCache {
     pub sig: String,
     pub target: String,
@@ -34,39 +53,153 @@ pub struct KeygenResponseCache {
 }
 
 impl KeygenClient {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         custom_domain: Option<String>,
         api_url: Option<String>,
         account_id: Option<String>,
-        verify_key: String,
+        verify_keys: Vec<VerifyKey>,
+        signature_algorithm: SignatureAlgorithm,
         version_header: Option<String>,
+        max_clock_drift: i64,
         cache_lifetime: i64,
+        grace_period: i64,
+        spki_pins: Vec<String>,
         user_agent: String,
-    ) -> Self {
+        storage: Storage,
+    ) -> Result<Self> {
         // client with default headers
-        let default_headers = Self::get_default_headers(user_agent, version_header);
+        let default_headers =
+            Self::get_default_headers(user_agent, version_header, signature_algorithm);
+
+        let mut http_client = reqwest::Client::builder().default_headers(default_headers);
+
+        // pinned connections only
+        if !spki_pins.is_empty() {
+            let tls_config = pinning::tls_config(&spki_pins)?;
 
-        let http_client = reqwest::Client::builder()
-            .default_headers(default_headers)
-            .build()
-            .unwrap_or_default();
+            http_client = http_client.use_preconfigured_tls(tls_config);
+        }
+
+        // don't silently fall back to an unpinned client
+        let http_client = if spki_pins.is_empty() {
+            http_client.build().unwrap_or_default()
+        } else {
+            http_client.build()?
+        };
+
+        // parse verify keys once.
+        // a malformed key is a config error, not an invalid signature later on
+        let verify_keys = verify_keys
+            .iter()
+            .enumerate()
+            .map(|(index, verify_key)| {
+                verify_key.parse(index).map_err(|err| {
+                    Error::ParseErr(format!("Failed parsing verify key #{}: {}", index, err))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        Self {
+        if verify_keys.is_empty() {
+            return Err(Error::ParseErr("No verify key configured".into()));
+        }
+
+        // skew measured in an earlier session, until a validation is accepted in this one
+        let clock_skew = Self::read_seconds(storage.as_ref(), &StorageEntry::ClockSkew);
+        let last_seen = Self::read_seconds(storage.as_ref(), &StorageEntry::LastSeen);
+
+        Ok(Self {
             custom_domain,
             account_id,
-            verify_key,
+            verify_keys,
+            signature_algorithm,
             api_url,
             api_version: 1,
             http_client,
-            max_clock_drift: 5,
+            max_clock_drift,
+            clock_skew: AtomicI64::new(clock_skew),
+            last_seen: AtomicI64::new(last_seen),
+            storage,
             cache_lifetime,
+            grace_period,
+        })
+    }
+
+    fn read_seconds(storage: &dyn KeygenStorage, entry: &StorageEntry) -> i64 {
+        storage
+            .read(entry)
+            .unwrap_or_else(|err| {
+                dbg!(err);
+                None
+            })
+            .and_then(|seconds| seconds.trim().parse::<i64>().ok())
+            .unwrap_or_default()
+    }
+
+    // local time, corrected by the last measured skew against Keygen's server time.
+    // never earlier than the newest signed date already seen.
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        let last_seen =
+            DateTime::from_timestamp(self.last_seen.load(Ordering::Relaxed), 0).unwrap_or_default();
+
+        self.corrected_now().max(last_seen)
+    }
+
+    fn corrected_now(&self) -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(self.clock_skew.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn grace_period_enabled(&self) -> bool {
+        self.grace_period > 0
+    }
+
+    // seconds left of the offline grace period,
+    // counted from the signed validation timestamp
+    pub(crate) fn grace_remaining(&self, validated_at: &str) -> Option<i64> {
+        if !self.grace_period_enabled() {
+            return None;
         }
+
+        let validated_at = match DateTime::parse_from_rfc3339(validated_at) {
+            Ok(validated_at) => validated_at,
+            Err(err) => {
+                dbg!(err);
+                return None;
+            }
+        };
+
+        // the signed validation timestamp is a floor for the clock too
+        self.store_last_seen(validated_at.timestamp());
+
+        // a clock set back behind the last seen date ends the grace period,
+        // rather than restarting it
+        let last_seen = self.last_seen.load(Ordering::Relaxed);
+
+        if self.corrected_now().timestamp() < last_seen - CLOCK_SET_BACK_TOLERANCE {
+            return Some(0);
+        }
+
+        let seconds_since_validation = self.now().signed_duration_since(validated_at).num_seconds();
+
+        let grace_period = self.grace_period * 24 * 60 * 60;
+
+        Some((grace_period - seconds_since_validation).max(0))
     }
 
-    fn get_default_headers(user_agent: String, version_header: Option<String>) -> HeaderMap {
+    fn get_default_headers(
+        user_agent: String,
+        version_header: Option<String>,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> HeaderMap {
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, Self::get_header_value(&user_agent));
 
+        // ask Keygen to sign responses with the configured algorithm
+        headers.insert(
+            "Keygen-Accept-Signature",
+            Self::get_header_value(&format!("algorithm=\"{}\"", signature_algorithm)),
+        );
+
         if let Some(v) = version_header {
             headers.insert("Keygen-Version", Self::get_header_value(&v));
         }
@@ -93,6 +226,14 @@ impl KeygenClient {
         self.http_client.request(Method::POST, url)
     }
 
+    pub(crate) fn patch(&self, url: String) -> RequestBuilder {
+        self.http_client.request(Method::PATCH, url)
+    }
+
+    pub(crate) fn delete(&self, url: String) -> RequestBuilder {
+        self.http_client.request(Method::DELETE, url)
+    }
+
     pub(crate) fn build_url(&self, path: String, params: Option<Vec<(&str, &str)>>) -> Result<Url> {
         // get base url
         let base_url = self.get_base_url()?;
@@ -113,6 +254,10 @@ impl KeygenClient {
         Ok(url)
     }
 
+    pub(crate) fn validate_key_url(&self) -> Result<Url> {
+        self.build_url("licenses/actions/validate-key".into(), None)
+    }
+
     fn get_base_url(&self) -> Result<Url> {
         if let Some(custom_domain) = &self.custom_domain {
             let url = Url::parse(custom_domain).map_err(|_| {
@@ -168,15 +313,59 @@ impl KeygenClient {
         Ok((res_text, res_json))
     }
 
+    // a signed response, no older than the drift window
     pub(crate) fn verify_response(
         &self,
         req_method: String,
         req_url: Url,
         res_headers: HeaderMap,
         res_text: String,
+    ) -> Result<KeygenResponseCache> {
+        let res_cache = self.verify_signed_response(req_method, req_url, res_headers, res_text)?;
+
+        // a replayed response: older than the drift window by the corrected clock.
+        // a negative max_clock_drift disables the check
+        if self.max_clock_drift >= 0 {
+            let date_time = DateTime::parse_from_rfc2822(&res_cache.date)
+                .map_err(|_| Error::BadResponse("Invalid signature date".into()))?;
+
+            let seconds_since_response = self.now().signed_duration_since(date_time).num_seconds();
+
+            if seconds_since_response > self.max_clock_drift * 60 {
+                return Err(Error::BadResponse("Request date too old".into()));
+            }
+        }
+
+        Ok(res_cache)
+    }
+
+    // a validate-key response is fresh by its nonce, whatever its date:
+    // once the nonce matched, the caller measures the clock skew with apply_clock_skew()
+    pub(crate) fn verify_nonce_response(
+        &self,
+        req_method: String,
+        req_url: Url,
+        res_headers: HeaderMap,
+        res_text: String,
+    ) -> Result<KeygenResponseCache> {
+        self.verify_signed_response(req_method, req_url, res_headers, res_text)
+    }
+
+    fn verify_signed_response(
+        &self,
+        req_method: String,
+        req_url: Url,
+        res_headers: HeaderMap,
+        res_text: String,
     ) -> Result<KeygenResponseCache> {
         // get signature
-        let sig = KeygenSig::from_response(req_method, req_url, &res_headers, res_text.clone())?;
+        let sig = KeygenSig::from_response(
+            req_method,
+            req_url,
+            &res_headers,
+            res_text.clone(),
+            self.signature_algorithm,
+        )?;
 
         // get Digest from response headers
         let res_digest = res_headers
@@ -188,53 +377,143 @@ impl KeygenClient {
             return Err(Error::BadResponse("Digest didn't match".into()));
         }
 
-        // get duration since response date
+        // verify signature: only a signed date says anything about the clock
+        if let Err(err) =
+            self.verify_signature(self.signature_algorithm, sig.data(), sig.to_string())
+        {
+            dbg!(err);
+            return Err(Error::BadResponse("Invalid Signature".into()));
+        }
+
         let date_time = DateTime::parse_from_rfc2822(&sig.date())
             .map_err(|_| Error::BadResponse("Invalid signature date".into()))?;
 
-        let minutes_since_response = Utc::now().signed_duration_since(date_time).num_minutes();
+        // even a replayed response was signed by then
+        self.store_last_seen(date_time.timestamp());
 
-        // check request date
-        if self.max_clock_drift >= 0 && minutes_since_response > self.max_clock_drift {
-            return Err(Error::BadResponse("Request date too old".into()));
+        Ok(KeygenResponseCache {
+            sig: sig.to_string(),
+            target: sig.target(),
+            host: sig.host(),
+            date: sig.date(),
+            body: res_text,
+        })
+    }
+
+    // measure the clock skew from an accepted validate-key response:
+    // its nonce matched, so its date is the server's time now
+    pub(crate) fn apply_clock_skew(&self, res_cache: &KeygenResponseCache) -> Result<()> {
+        let url = self.validate_key_url()?;
+
+        if res_cache.target != KeygenSig::request_target(Method::POST.as_str(), &url) {
+            return Err(Error::BadResponse(
+                "Clock skew is only measured on validate-key responses".into(),
+            ));
         }
 
-        // verify signature
-        match self.verify_signature(sig.data(), sig.to_string()) {
-            Ok(()) => Ok(KeygenResponseCache {
-                sig: sig.to_string(),
-                target: sig.target(),
-                host: sig.host(),
-                date: sig.date(),
-                body: res_text,
-            }),
-            Err(err) => {
+        let date_time = DateTime::parse_from_rfc2822(&res_cache.date)
+            .map_err(|_| Error::BadResponse("Invalid signature date".into()))?;
+
+        let clock_skew = date_time.signed_duration_since(Utc::now()).num_seconds();
+        let previous = self.clock_skew.swap(clock_skew, Ordering::Relaxed);
+
+        // kept for the expiry math on the next startup, before any request is made
+        if (clock_skew - previous).abs() > CLOCK_PERSIST_THRESHOLD {
+            if let Err(err) = self
+                .storage
+                .write(&StorageEntry::ClockSkew, &clock_skew.to_string())
+            {
                 dbg!(err);
-                Err(Error::BadResponse("Invalid Signature".into()))
             }
         }
+
+        Ok(())
     }
 
-    pub(crate) fn verify_response_cache(
+    fn store_last_seen(&self, timestamp: i64) {
+        let previous = self.last_seen.fetch_max(timestamp, Ordering::Relaxed);
+
+        if timestamp - previous > CLOCK_PERSIST_THRESHOLD {
+            if let Err(err) = self
+                .storage
+                .write(&StorageEntry::LastSeen, &timestamp.to_string())
+            {
+                dbg!(err);
+            }
+        }
+    }
+
+    // error responses are signed too.
+    // don't trust error codes from an unsigned (possibly forged) response.
+    pub(crate) fn verify_err_response(
         &self,
-        res_cache: KeygenResponseCache,
-    ) -> Result<LicenseResponse> {
-        let res_text = res_cache.body.clone();
-        let sig = KeygenSig::from_response_cache(res_cache);
+        req_method: String,
+        req_url: Url,
+        res_status: StatusCode,
+        res_headers: HeaderMap,
+        res_text: String,
+        res_json: serde_json::Value,
+    ) -> Error {
+        if !res_status.is_client_error() {
+            return parse_err_json(res_status, res_json);
+        }
+
+        match self.verify_response(req_method, req_url, res_headers, res_text) {
+            Ok(_) => parse_err_json(res_status, res_json),
+            Err(err) => {
+                dbg!(err);
+                Error::UnverifiedErr(format!(
+                    "Failed verifying error response signature ({})",
+                    res_status
+                ))
+            }
+        }
+    }
 
+    pub(crate) fn response_cache_expired(&self, res_cache: &KeygenResponseCache) -> Result<bool> {
         // get duration since response date
-        let date_time = DateTime::parse_from_rfc2822(&sig.date())
+        let date_time = DateTime::parse_from_rfc2822(&res_cache.date)
             .map_err(|_| Error::BadCache("Failed parsing cached response date".into()))?;
 
-        let minutes_since_response = Utc::now().signed_duration_since(date_time).num_minutes();
+        let minutes_since_response = self.now().signed_duration_since(date_time).num_minutes();
 
+        Ok(minutes_since_response > self.cache_lifetime)
+    }
+
+    pub(crate) fn verify_response_cache(
+        &self,
+        res_cache: KeygenResponseCache,
+    ) -> Result<LicenseResponse> {
         // check request date
-        if minutes_since_response > self.cache_lifetime {
+        if self.response_cache_expired(&res_cache)? {
             return Err(Error::BadCache("Validation cache has expired".into()));
         }
 
+        self.verify_validation_record(res_cache)
+    }
+
+    // a stored validate-key response, regardless of its age
+    pub(crate) fn verify_validation_record(
+        &self,
+        res_cache: KeygenResponseCache,
+    ) -> Result<LicenseResponse> {
+        // must be a validate-key response from the configured api host
+        let url = self.validate_key_url()?;
+        let target = KeygenSig::request_target(Method::POST.as_str(), &url);
+
+        if res_cache.target != target {
+            return Err(Error::BadCache("Unexpected cached response target".into()));
+        }
+
+        if url.host_str() != Some(res_cache.host.as_str()) {
+            return Err(Error::BadCache("Unexpected cached response host".into()));
+        }
+
+        let res_text = res_cache.body.clone();
+        let sig = KeygenSig::from_response_cache(res_cache);
+
         // verify signature
-        match self.verify_signature(sig.data(), sig.to_string()) {
+        match self.verify_signature(self.signature_algorithm, sig.data(), sig.to_string()) {
             Ok(()) => {
                 // get json from res text
                 let res_json: serde_json::Value = serde_json::from_str(&res_text)
@@ -255,30 +534,114 @@ impl KeygenClient {
 
     pub(crate) fn verify_signature(
         &self,
+        algorithm: SignatureAlgorithm,
         data: String,
         signature: String,
     ) -> std::result::Result<(), Error> {
-        // init key
-        let key = match <[u8; PUBLIC_KEY_LENGTH]>::from_hex(self.verify_key.as_str()) {
-            Ok(bytes) => VerifyingKey::from_bytes(&bytes),
-            Err(_) => return Err(Error::ParseErr("Failed parsing verify key to bytes".into())),
-        }
-        .map_err(|_| Error::ParseErr("Failed parsing verifying key".into()))?;
-
         // decode signature
         let sig = base64::engine::general_purpose::STANDARD
             .decode(signature.as_str())
             .map_err(|_| Error::ParseErr("Failed decoding signature".into()))?;
 
-        let sig: [u8; SIGNATURE_LENGTH] = match sig.try_into() {
-            Ok(sig) => sig,
-            Err(_) => return Err(Error::ParseErr("Invalid signature format".into())),
-        };
-
-        // verify
-        match key.verify(data.as_bytes(), &sig.into()) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(Error::ParseErr("Invalid signature".into())),
+        // try verify keys in order, skipping the expired ones
+        let now = self.now();
+
+        for verify_key in self.verify_keys.iter().filter(|key| key.is_usable(now)) {
+            if algorithm
+                .verify(&verify_key.key, data.as_bytes(), &sig)
+                .is_ok()
+            {
+                #[cfg(debug_assertions)]
+                dbg!(format!(
+                    "Signature verified with verify key #{}",
+                    verify_key.index
+                ));
+                return Ok(());
+            }
         }
+
+        Err(Error::ParseErr("Invalid signature".into()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::KeygenClient;
+    use crate::{
+        client::{sig::SignatureAlgorithm, verify_key::VerifyKey},
+        storage::{KeygenStorage, MemoryStorage, Storage, StorageEntry},
+    };
+    use chrono::{Duration, SecondsFormat, Utc};
+    use ed25519_dalek::SigningKey;
+    use std::sync::Arc;
+
+    static DAY: i64 = 24 * 60 * 60;
+
+    pub(crate) fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    pub(crate) fn client(grace_period: i64, storage: Storage) -> KeygenClient {
+        let verify_key = hex::encode(signing_key().verifying_key().as_bytes());
+
+        KeygenClient::new(
+            None,
+            Some("https://api.keygen.sh".into()),
+            Some("account".into()),
+            vec![VerifyKey::new(verify_key, None)],
+            SignatureAlgorithm::Ed25519,
+            None,
+            5,
+            240,
+            grace_period,
+            vec![],
+            "test".into(),
+            storage,
+        )
+        .unwrap()
+    }
+
+    fn days_ago(days: i64) -> String {
+        (Utc::now() - Duration::days(days)).to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    #[test]
+    fn grace_remaining_counts_down_from_validation() {
+        let client = client(7, Arc::new(MemoryStorage::new()));
+
+        let remaining = client.grace_remaining(&days_ago(2)).unwrap();
+
+        assert!((5 * DAY - 60..=5 * DAY).contains(&remaining));
+        assert_eq!(client.grace_remaining(&days_ago(8)), Some(0));
+    }
+
+    #[test]
+    fn grace_remaining_disabled_or_invalid() {
+        let disabled = client(0, Arc::new(MemoryStorage::new()));
+        assert_eq!(disabled.grace_remaining(&days_ago(1)), None);
+
+        let enabled = client(7, Arc::new(MemoryStorage::new()));
+        assert_eq!(enabled.grace_remaining("not a date"), None);
+    }
+
+    #[test]
+    fn grace_remaining_ends_when_clock_set_back() {
+        // a signed date seen a day ahead of the local clock
+        let storage = Arc::new(MemoryStorage::new());
+        let last_seen = (Utc::now() + Duration::days(1)).timestamp();
+        storage
+            .write(&StorageEntry::LastSeen, &last_seen.to_string())
+            .unwrap();
+
+        let client = client(7, storage);
+
+        assert_eq!(client.grace_remaining(&days_ago(2)), Some(0));
+    }
+
+    #[test]
+    fn grace_remaining_ends_when_validated_in_the_future() {
+        let client = client(7, Arc::new(MemoryStorage::new()));
+
+        assert_eq!(client.grace_remaining(&days_ago(-1)), Some(0));
     }
 }
@@ -0,0 +1,206 @@
+use crate::err::Error;
+use base64::Engine;
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::ring,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+    SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::{error::Error as StdError, fmt, sync::Arc};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+// none of the server's certificates matched the configured pins
+#[derive(Debug)]
+pub struct SpkiPinMismatch;
+
+impl fmt::Display for SpkiPinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server certificate didn't match any SPKI pin")
+    }
+}
+
+impl StdError for SpkiPinMismatch {}
+
+#[derive(Debug)]
+struct SpkiPinVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>, // sha256 of the DER encoded SubjectPublicKeyInfo
+}
+
+impl SpkiPinVerifier {
+    fn matches(&self, cert: &CertificateDer<'_>) -> bool {
+        let Ok((_, cert)) = X509Certificate::from_der(cert.as_ref()) else {
+            return false;
+        };
+
+        let digest: [u8; 32] = Sha256::digest(cert.public_key().raw).into();
+
+        self.pins.contains(&digest)
+    }
+}
+
+impl ServerCertVerifier for SpkiPinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // regular chain validation first
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        // any certificate in the chain can be pinned
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates.iter())
+            .any(|cert| self.matches(cert));
+
+        if !pinned {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                OtherError(Arc::new(SpkiPinMismatch)),
+            )));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+// pins are base64 encoded sha256 digests of the SPKI,
+// e.g. the output of:
+// openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64
+// an invalid pin is a config error: skipping it could leave no pin to match
+pub(crate) fn tls_config(spki_pins: &[String]) -> crate::Result<ClientConfig> {
+    let pins = spki_pins
+        .iter()
+        .map(|pin| {
+            let pin = pin.trim().trim_start_matches("sha256/");
+
+            base64::engine::general_purpose::STANDARD
+                .decode(pin)
+                .ok()
+                .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+                .ok_or_else(|| {
+                    Error::PinErr(format!(
+                        "Invalid SPKI pin, expected a base64 encoded sha256 digest: {}",
+                        pin
+                    ))
+                })
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    let setup_err =
+        |err: rustls::Error| Error::PinErr(format!("Failed setting up TLS pinning: {}", err));
+
+    let provider = Arc::new(ring::default_provider());
+
+    let roots = Arc::new(RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    });
+
+    let inner = WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+        .build()
+        .map_err(|err| setup_err(rustls::Error::General(err.to_string())))?;
+
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(setup_err)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SpkiPinVerifier { inner, pins }))
+        .with_no_client_auth();
+
+    Ok(config)
+}
+
+// walk the error chain looking for a pin failure.
+// io::Error::source() skips its inner error, so look into it explicitly.
+pub(crate) fn is_pin_mismatch(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if err.is::<SpkiPinMismatch>() {
+            return true;
+        }
+
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) =
+            err.downcast_ref::<rustls::Error>()
+        {
+            if other.0.is::<SpkiPinMismatch>() {
+                return true;
+            }
+        }
+
+        if let Some(inner) = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io_err| io_err.get_ref())
+        {
+            if is_pin_mismatch(inner) {
+                return true;
+            }
+        }
+
+        source = err.source();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tls_config;
+    use crate::err::Error;
+    use base64::Engine;
+
+    fn pin(digest: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(digest)
+    }
+
+    #[test]
+    fn tls_config_accepts_sha256_pins() {
+        let pins = vec![pin(&[1; 32]), format!("sha256/{}", pin(&[2; 32]))];
+
+        assert!(tls_config(&pins).is_ok());
+    }
+
+    #[test]
+    fn tls_config_rejects_invalid_pins() {
+        for invalid in ["not base64!", &pin(&[1; 20]), ""] {
+            let pins = vec![pin(&[1; 32]), invalid.to_string()];
+
+            assert!(matches!(tls_config(&pins), Err(Error::PinErr(_))));
+        }
+    }
+}
@@ -1,8 +1,81 @@
-use crate::{client::KeygenResponseCache, err::Error, Result};
+use crate::{
+    client::{verify_key::PublicKey, KeygenResponseCache},
+    err::Error,
+    Result,
+};
 use base64::Engine;
+use ed25519_dalek::{Verifier, SIGNATURE_LENGTH};
 use reqwest::{header::HeaderMap, Url};
 use sha2::{Digest, Sha256};
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+    RsaPssSha256,
+    RsaSha256,
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let algorithm = match self {
+            Self::Ed25519 => "ed25519",
+            Self::RsaPssSha256 => "rsa-pss-sha256",
+            Self::RsaSha256 => "rsa-sha256",
+        };
+
+        write!(f, "{}", algorithm)
+    }
+}
+
+impl FromStr for SignatureAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ed25519" => Ok(Self::Ed25519),
+            "rsa-pss-sha256" => Ok(Self::RsaPssSha256),
+            "rsa-sha256" => Ok(Self::RsaSha256),
+            _ => Err(Error::BadResponse("Unsupported algorithm".into())),
+        }
+    }
+}
+
+impl SignatureAlgorithm {
+    pub(crate) fn verify(&self, key: &PublicKey, data: &[u8], signature: &[u8]) -> Result<()> {
+        match (self, key) {
+            (Self::Ed25519, PublicKey::Ed25519(key)) => {
+                let sig: [u8; SIGNATURE_LENGTH] = match signature.try_into() {
+                    Ok(sig) => sig,
+                    Err(_) => return Err(Error::ParseErr("Invalid signature format".into())),
+                };
+
+                key.verify(data, &sig.into())
+                    .map_err(|_| Error::ParseErr("Invalid signature".into()))
+            }
+            (Self::RsaPssSha256, PublicKey::Rsa(key)) => {
+                let key = rsa::pss::VerifyingKey::<Sha256>::new(key.clone());
+                let sig = rsa::pss::Signature::try_from(signature)
+                    .map_err(|_| Error::ParseErr("Invalid signature format".into()))?;
+
+                key.verify(data, &sig)
+                    .map_err(|_| Error::ParseErr("Invalid signature".into()))
+            }
+            (Self::RsaSha256, PublicKey::Rsa(key)) => {
+                let key = rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key.clone());
+                let sig = rsa::pkcs1v15::Signature::try_from(signature)
+                    .map_err(|_| Error::ParseErr("Invalid signature format".into()))?;
+
+                key.verify(data, &sig)
+                    .map_err(|_| Error::ParseErr("Invalid signature".into()))
+            }
+            _ => Err(Error::ParseErr(
+                "Verify key doesn't match signature algorithm".into(),
+            )),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct KeygenSig {
@@ -55,9 +128,10 @@ impl KeygenSig {
         req_url: Url,
         res_headers: &HeaderMap,
         res_text: String,
+        algorithm: SignatureAlgorithm,
     ) -> Result<Self> {
         // parse signature
-        let sig = Self::parse_signature(res_headers)?;
+        let sig = Self::parse_signature(res_headers, algorithm)?;
 
         // build data
         let data = Self::build_signature_data(req_method, req_url, res_headers, res_text)?;
@@ -86,7 +160,7 @@ impl KeygenSig {
         }
     }
 
-    fn parse_signature(res_headers: &HeaderMap) -> Result<String> {
+    fn parse_signature(res_headers: &HeaderMap, expected: SignatureAlgorithm) -> Result<String> {
         // get Keygen-Signature header
         let parameterized_header = res_headers
             .get("Keygen-Signature")
@@ -103,8 +177,9 @@ impl KeygenSig {
             ));
         }
 
-        if !"ed25519".to_string().eq(&algorithm.unwrap()) {
-            return Err(Error::BadResponse("Unsupported algorithm".into()));
+        // must match the negotiated algorithm
+        if SignatureAlgorithm::from_str(&algorithm.unwrap())? != expected {
+            return Err(Error::BadResponse("Unexpected signature algorithm".into()));
         }
 
         if signature.is_none() {
@@ -141,6 +216,16 @@ impl KeygenSig {
         (algorithm, signature)
     }
 
+    pub fn request_target(req_method: &str, req_url: &Url) -> String {
+        let method = req_method.to_lowercase();
+        let mut path = req_url.path().to_string();
+        if let Some(query) = req_url.query() {
+            path.push_str(format!("?{}", query).as_str())
+        }
+
+        format!("{} {}", method, path)
+    }
+
     fn build_signature_data(
         req_method: String,
         req_url: Url,
@@ -148,12 +233,7 @@ impl KeygenSig {
         res_text: String,
     ) -> Result<KeygenSigData> {
         // get target
-        let method = req_method.to_lowercase();
-        let mut path = req_url.path().to_string();
-        if let Some(query) = req_url.query() {
-            path.push_str(format!("?{}", query).as_str())
-        }
-        let target = format!("{} {}", method, path);
+        let target = Self::request_target(&req_method, &req_url);
 
         // get host
         let host = req_url
@@ -186,3 +266,71 @@ impl KeygenSig {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SignatureAlgorithm;
+    use crate::client::verify_key::PublicKey;
+    use rsa::{
+        signature::{RandomizedSigner, SignatureEncoding, Signer},
+        RsaPrivateKey,
+    };
+    use sha2::Sha256;
+
+    static DATA: &[u8] = b"(request-target): post /v1/licenses/actions/validate-key";
+
+    fn rsa_key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap()
+    }
+
+    #[test]
+    fn verify_rsa_pss_sha256() {
+        let private_key = rsa_key();
+        let public_key = PublicKey::Rsa(private_key.to_public_key());
+
+        let signing_key = rsa::pss::BlindedSigningKey::<Sha256>::new(private_key);
+        let signature = signing_key
+            .sign_with_rng(&mut rand::thread_rng(), DATA)
+            .to_vec();
+
+        let algorithm = SignatureAlgorithm::RsaPssSha256;
+
+        assert!(algorithm.verify(&public_key, DATA, &signature).is_ok());
+        assert!(algorithm
+            .verify(&public_key, b"tampered", &signature)
+            .is_err());
+        // not a PKCS#1 v1.5 signature
+        assert!(SignatureAlgorithm::RsaSha256
+            .verify(&public_key, DATA, &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn verify_rsa_sha256() {
+        let private_key = rsa_key();
+        let public_key = PublicKey::Rsa(private_key.to_public_key());
+
+        let signing_key = rsa::pkcs1v15::SigningKey::<Sha256>::new(private_key);
+        let signature = signing_key.sign(DATA).to_vec();
+
+        let algorithm = SignatureAlgorithm::RsaSha256;
+
+        assert!(algorithm.verify(&public_key, DATA, &signature).is_ok());
+        assert!(algorithm
+            .verify(&public_key, b"tampered", &signature)
+            .is_err());
+        // not a PSS signature
+        assert!(SignatureAlgorithm::RsaPssSha256
+            .verify(&public_key, DATA, &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn verify_rejects_mismatched_key_type() {
+        let public_key = PublicKey::Rsa(rsa_key().to_public_key());
+
+        assert!(SignatureAlgorithm::Ed25519
+            .verify(&public_key, DATA, &[0; 64])
+            .is_err());
+    }
+}
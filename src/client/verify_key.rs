@@ -0,0 +1,89 @@
+use crate::{err::Error, Result};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{VerifyingKey, PUBLIC_KEY_LENGTH};
+use hex::FromHex;
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, RsaPublicKey};
+
+#[derive(Debug, Clone)]
+pub struct VerifyKey {
+    pub key: String,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub enum PublicKey {
+    Ed25519(VerifyingKey),
+    Rsa(RsaPublicKey),
+}
+
+// a verify key, parsed once on plugin setup
+#[derive(Debug)]
+pub struct ParsedVerifyKey {
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub index: usize, // position in the configured key list
+    pub key: PublicKey,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl VerifyKey {
+    pub fn new(key: impl Into<String>, valid_until: Option<DateTime<Utc>>) -> Self {
+        Self {
+            key: key.into(),
+            valid_until,
+        }
+    }
+
+    // ed25519: hex encoded verify key
+    // rsa: PEM encoded public key (SPKI or PKCS#1)
+    pub(crate) fn parse(&self, index: usize) -> Result<ParsedVerifyKey> {
+        let key = self.key.trim();
+
+        let key = if key.starts_with("-----BEGIN") {
+            let key = RsaPublicKey::from_public_key_pem(key)
+                .or_else(|_| RsaPublicKey::from_pkcs1_pem(key))
+                .map_err(|_| Error::ParseErr("Failed parsing RSA verify key".into()))?;
+
+            PublicKey::Rsa(key)
+        } else {
+            let key = match <[u8; PUBLIC_KEY_LENGTH]>::from_hex(key) {
+                Ok(bytes) => VerifyingKey::from_bytes(&bytes),
+                Err(_) => return Err(Error::ParseErr("Failed parsing verify key to bytes".into())),
+            }
+            .map_err(|_| Error::ParseErr("Failed parsing verifying key".into()))?;
+
+            PublicKey::Ed25519(key)
+        };
+
+        Ok(ParsedVerifyKey {
+            index,
+            key,
+            valid_until: self.valid_until,
+        })
+    }
+}
+
+// a key without a valid_until date
+impl From<String> for VerifyKey {
+    fn from(key: String) -> Self {
+        Self::new(key, None)
+    }
+}
+
+impl From<&str> for VerifyKey {
+    fn from(key: &str) -> Self {
+        Self::new(key, None)
+    }
+}
+
+impl From<&String> for VerifyKey {
+    fn from(key: &String) -> Self {
+        Self::new(key.as_str(), None)
+    }
+}
+
+impl ParsedVerifyKey {
+    pub(crate) fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until
+            .map_or(true, |valid_until| now < valid_until)
+    }
+}
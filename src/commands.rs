@@ -1,8 +1,10 @@
 use crate::{
     client::KeygenClient,
     err::ErrorSummary,
-    licensed::{license::License, LicensedState},
+    events,
+    licensed::{license::License, LicensedState, VALIDATE_KEY_TIMEOUT},
     machine::Machine,
+    storage::Storage,
 };
 use tauri::{command, AppHandle, Runtime, State, Window};
 use tokio::sync::Mutex;
@@ -21,10 +23,14 @@ pub async fn get_license<R: Runtime>(
 
 #[command]
 pub async fn get_license_key<R: Runtime>(
-    app: AppHandle<R>,
+    _app: AppHandle<R>,
     _window: Window<R>,
+    storage: State<'_, Storage>,
+    machine: State<'_, Mutex<Machine>>,
 ) -> Result<Option<String>> {
-    match LicensedState::get_cached_license_key(&app) {
+    let machine = machine.lock().await;
+
+    match LicensedState::get_cached_license_key(storage.as_ref(), &machine) {
         Ok(key) => Ok(key),
         Err(err) => {
             dbg!(&err);
@@ -38,6 +44,7 @@ pub async fn get_license_key<R: Runtime>(
 pub async fn validate_key<R: Runtime>(
     app: AppHandle<R>,
     _window: Window<R>,
+    storage: State<'_, Storage>,
     machine: State<'_, Mutex<Machine>>,
     client: State<'_, Mutex<KeygenClient>>,
     licensed_state: State<'_, Mutex<LicensedState>>,
@@ -45,61 +52,68 @@ pub async fn validate_key<R: Runtime>(
     entitlements: Vec<String>,
     cache_valid_response: bool,
 ) -> Result<License> {
-    let machine = machine.lock().await;
+    let mut machine = machine.lock().await;
     let client = client.lock().await;
 
     let mut licensed_state = licensed_state.lock().await;
 
     match licensed_state
-        .validate_key(key, entitlements, &machine, &client)
+        .validate_and_update(
+            &app,
+            storage.as_ref(),
+            &mut machine,
+            &client,
+            key,
+            entitlements,
+            cache_valid_response,
+            VALIDATE_KEY_TIMEOUT,
+        )
         .await
     {
-        Ok((license, res_cache)) => {
-            // cache valid response
-            if license.valid && cache_valid_response && license.expiry.is_some() {
-                LicensedState::cache_response(&app, &license.key, res_cache)?;
-            }
-
-            // update state
-            licensed_state.update(Some(license.clone()));
-
-            // cache license key
-            LicensedState::cache_license_key(&license.key, &app)?;
-
-            Ok(license)
-        }
+        Ok(license) => Ok(license),
         Err(err) => {
             dbg!(&err);
-            Err(err.into())
+            let err = err.into();
+            events::error(&app, &licensed_state, &err);
+            Err(err)
         }
     }
 }
 
 #[command]
 pub async fn activate<R: Runtime>(
+    app: AppHandle<R>,
     _window: Window<R>,
+    storage: State<'_, Storage>,
     machine: State<'_, Mutex<Machine>>,
     client: State<'_, Mutex<KeygenClient>>,
     licensed_state: State<'_, Mutex<LicensedState>>,
 ) -> Result<()> {
-    let machine = machine.lock().await;
+    let mut machine = machine.lock().await;
     let client = client.lock().await;
 
     let mut licensed_state = licensed_state.lock().await;
 
-    match machine.activate(&mut licensed_state, &client).await {
+    match machine
+        .activate(&mut licensed_state, &client, storage.as_ref())
+        .await
+    {
         Ok(()) => Ok(()),
         Err(err) => {
             dbg!(&err);
-            Err(err.into())
+            let err = err.into();
+            events::error(&app, &licensed_state, &err);
+            Err(err)
         }
     }
 }
 
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn checkout_machine<R: Runtime>(
     app: AppHandle<R>,
     _window: Window<R>,
+    storage: State<'_, Storage>,
     machine: State<'_, Mutex<Machine>>,
     licensed_state: State<'_, Mutex<LicensedState>>,
     client: State<'_, Mutex<KeygenClient>>,
@@ -112,13 +126,49 @@ pub async fn checkout_machine<R: Runtime>(
     let licensed_state = licensed_state.lock().await;
 
     match machine
-        .checkout(&licensed_state, &client, &app, ttl_seconds, ttl_forever)
+        .checkout(
+            &licensed_state,
+            &client,
+            storage.as_ref(),
+            ttl_seconds,
+            ttl_forever,
+        )
         .await
     {
         Ok(()) => Ok(()),
         Err(err) => {
             dbg!(&err);
-            Err(err.into())
+            let err = err.into();
+            events::error(&app, &licensed_state, &err);
+            Err(err)
+        }
+    }
+}
+
+#[command]
+pub async fn update_machine<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    storage: State<'_, Storage>,
+    machine: State<'_, Mutex<Machine>>,
+    licensed_state: State<'_, Mutex<LicensedState>>,
+    client: State<'_, Mutex<KeygenClient>>,
+) -> Result<()> {
+    let machine = machine.lock().await;
+    let client = client.lock().await;
+
+    let licensed_state = licensed_state.lock().await;
+
+    match machine
+        .update(&licensed_state, &client, storage.as_ref())
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            dbg!(&err);
+            let err = err.into();
+            events::error(&app, &licensed_state, &err);
+            Err(err)
         }
     }
 }
@@ -127,22 +177,29 @@ pub async fn checkout_machine<R: Runtime>(
 pub async fn reset_license<R: Runtime>(
     app: AppHandle<R>,
     _window: Window<R>,
+    storage: State<'_, Storage>,
+    client: State<'_, Mutex<KeygenClient>>,
     licensed_state: State<'_, Mutex<LicensedState>>,
 ) -> Result<()> {
+    let client = client.lock().await;
+
     let mut licensed_state = licensed_state.lock().await;
 
     // reset state
-    licensed_state.update(None);
+    events::update_license(&app, &mut licensed_state, None, client.now());
 
     // delete offline licenses
-    Machine::remove_machine_file(&app)?;
-    LicensedState::clear_response_cache(&app)?;
+    LicensedState::remove_offline_licenses(storage.as_ref())?;
 
     Ok(())
 }
 
 #[command]
-pub async fn reset_license_key<R: Runtime>(app: AppHandle<R>, _window: Window<R>) -> Result<()> {
-    LicensedState::remove_cached_license_key(&app)?;
+pub async fn reset_license_key<R: Runtime>(
+    _app: AppHandle<R>,
+    _window: Window<R>,
+    storage: State<'_, Storage>,
+) -> Result<()> {
+    LicensedState::remove_cached_license_key(storage.as_ref())?;
     Ok(())
 }
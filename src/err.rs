@@ -1,3 +1,4 @@
+use crate::client::pinning;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
@@ -7,12 +8,16 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Path Error: {0}")]
     PathErr(String),
+    #[error("Storage Error: {0}")]
+    StorageErr(String),
     #[error("Http error: {0}")]
     #[allow(clippy::enum_variant_names)]
     HttpError(String),
     #[error("Failed processing a request: {0}")]
     #[allow(clippy::enum_variant_names)]
-    RequestError(#[from] reqwest::Error),
+    RequestError(reqwest::Error),
+    #[error("TLS pinning error: {0}")]
+    PinErr(String),
     #[error("Bad response: {0}")]
     BadResponse(String),
     #[error("Bad cache: {0}")]
@@ -23,9 +28,23 @@ pub enum Error {
     LicenseErr { code: String, detail: String },
     #[error("Keygen API Error: {code:?}: {detail:?}")]
     ApiErr { code: String, detail: String },
+    #[error("Core limit exceeded: {0}")]
+    CoreLimitExceeded(String),
+    #[error("Unverified Keygen API Error: {0}")]
+    UnverifiedErr(String),
 }
 
-#[derive(Debug, Serialize)]
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        if pinning::is_pin_mismatch(&value) {
+            return Error::PinErr("Server certificate didn't match any SPKI pin".into());
+        }
+
+        Error::RequestError(value)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
 pub struct ErrorSummary {
     pub code: String,
     pub detail: String,
@@ -43,6 +62,10 @@ impl From<Error> for ErrorSummary {
                 code: "REQUEST_ERROR".into(),
                 detail: "Failed sending request: Check your internet".into(),
             },
+            Error::PinErr(detail) => Self {
+                code: "TLS_PIN_MISMATCH".into(),
+                detail,
+            },
             Error::BadResponse(detail) => Self {
                 code: "BAD_RESPONSE".into(),
                 detail,
@@ -53,10 +76,19 @@ impl From<Error> for ErrorSummary {
             },
             Error::LicenseErr { code, detail } => Self { code, detail },
             Error::ApiErr { code, detail } => Self { code, detail },
+            Error::CoreLimitExceeded(detail) => Self {
+                code: "CORE_LIMIT_EXCEEDED".into(),
+                detail,
+            },
+            Error::UnverifiedErr(detail) => Self {
+                code: "UNVERIFIED_ERROR".into(),
+                detail,
+            },
             err => {
                 let msg = match err {
                     Error::Io(err) => err.to_string(),
                     Error::PathErr(err) => err,
+                    Error::StorageErr(err) => err,
                     Error::ParseErr(err) => err,
                     _ => "".into(),
                 };
@@ -94,6 +126,14 @@ pub fn parse_err_json(status_code: StatusCode, err: serde_json::Value) -> Error
                         let code = errs[0].code.clone().unwrap_or_default();
                         let detail = errs[0].detail.clone().unwrap_or_default();
 
+                        // the machine has more cores than the policy allows
+                        if code == "MACHINE_CORE_LIMIT_EXCEEDED"
+                            || code == "CORE_LIMIT_EXCEEDED"
+                            || code == "TOO_MANY_CORES"
+                        {
+                            return Error::CoreLimitExceeded(detail);
+                        }
+
                         return Error::ApiErr { code, detail };
                     }
                 }
@@ -0,0 +1,89 @@
+use crate::{
+    capabilities,
+    err::ErrorSummary,
+    licensed::{license::License, LicensedState},
+};
+use chrono::{DateTime, Utc};
+use tauri::{AppHandle, Emitter, Runtime};
+
+pub(crate) static LICENSE_CHANGED_EVENT: &str = "keygen://license-changed";
+pub(crate) static EXPIRING_EVENT: &str = "keygen://expiring";
+pub(crate) static OFFLINE_EVENT: &str = "keygen://offline";
+pub(crate) static ERROR_EVENT: &str = "keygen://error";
+
+// how close to its expiry a license is considered expiring
+static EXPIRING_WITHIN_HOURS: i64 = 72;
+
+// set the licensed state, and let every window know when it changed
+pub(crate) fn update_license<R: Runtime>(
+    app: &AppHandle<R>,
+    licensed_state: &mut LicensedState,
+    license: Option<License>,
+    now: DateTime<Utc>,
+) {
+    if !licensed_state.update(license) {
+        return;
+    }
+
+    let license = licensed_state.get_license();
+
+    capabilities::grant(app, license.as_ref());
+
+    emit(app, LICENSE_CHANGED_EVENT, &license);
+
+    if let Some(license) = license.filter(|license| is_expiring(license, now)) {
+        emit(app, EXPIRING_EVENT, &license);
+    }
+}
+
+// the license is still the same, but is getting close to its expiry
+pub(crate) fn expiring<R: Runtime>(app: &AppHandle<R>, license: &License) {
+    emit(app, EXPIRING_EVENT, license);
+}
+
+// failed requests: offline when Keygen couldn't be reached
+pub(crate) fn error<R: Runtime>(
+    app: &AppHandle<R>,
+    licensed_state: &LicensedState,
+    err: &ErrorSummary,
+) {
+    if err.code == "REQUEST_ERROR" {
+        offline(app, licensed_state);
+    }
+
+    emit(app, ERROR_EVENT, err);
+}
+
+// Keygen couldn't be reached, still running on the current license
+pub(crate) fn offline<R: Runtime>(app: &AppHandle<R>, licensed_state: &LicensedState) {
+    emit(app, OFFLINE_EVENT, &licensed_state.get_license());
+}
+
+pub(crate) fn is_expiring(license: &License, now: DateTime<Utc>) -> bool {
+    if !license.valid {
+        return false;
+    }
+
+    // offline, running out of grace
+    if license.code == "GRACE_PERIOD" {
+        return true;
+    }
+
+    license
+        .expiry
+        .as_ref()
+        .is_some_and(|expiry| expires_soon(expiry, now))
+}
+
+pub(crate) fn expires_soon(expiry: &str, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(expiry).is_ok_and(|expiry| {
+        let hours_to_expiry = expiry.signed_duration_since(now).num_hours();
+        (0..EXPIRING_WITHIN_HOURS).contains(&hours_to_expiry)
+    })
+}
+
+fn emit<R: Runtime, S: serde::Serialize + Clone>(app: &AppHandle<R>, event: &str, payload: &S) {
+    if let Err(err) = app.emit(event, payload.clone()) {
+        dbg!(err);
+    }
+}
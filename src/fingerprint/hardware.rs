@@ -0,0 +1,107 @@
+use super::FingerprintProvider;
+use crate::{storage::KeygenStorage, Result};
+use sha2::{Digest, Sha256};
+
+// '/etc/machine-id', IOPlatformUUID, or the windows MachineGuid
+#[derive(Debug, Clone, Copy)]
+pub struct MachineUidFingerprint;
+
+impl FingerprintProvider for MachineUidFingerprint {
+    fn fingerprint(&self, _storage: &dyn KeygenStorage) -> Result<Option<String>> {
+        match machine_uid::get() {
+            Ok(uid) if !uid.trim().is_empty() => Ok(Some(uid.trim().to_string())),
+            Ok(_) => Ok(None),
+            Err(err) => {
+                dbg!(err.to_string());
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareSource {
+    MachineUid,
+    Arch,
+    Platform,
+    // opt-in only: users rename hosts
+    Hostname,
+    // opt-in only: changes with cgroup quotas and CPU affinity
+    Cpus,
+}
+
+impl HardwareSource {
+    fn read(&self) -> Option<String> {
+        let value = match self {
+            Self::MachineUid => machine_uid::get().ok()?,
+            Self::Hostname => whoami::fallible::hostname().ok()?,
+            Self::Arch => whoami::arch().to_string(),
+            Self::Cpus => std::thread::available_parallelism().ok()?.to_string(),
+            Self::Platform => whoami::platform().to_string(),
+        };
+
+        let value = value.trim().to_string();
+
+        (!value.is_empty()).then_some(value)
+    }
+
+    // arch, platform and cpu count are shared by many machines: they don't tell them apart
+    fn is_machine_specific(&self) -> bool {
+        matches!(self, Self::MachineUid | Self::Hostname)
+    }
+}
+
+// sha256 of several hardware sources.
+// unavailable sources are left out, but the machine id or hostname must be read:
+// the other sources alone would give many machines the same fingerprint.
+// the default leaves out the volatile ones: a changed fingerprint moves the activation.
+#[derive(Debug, Clone)]
+pub struct CompositeFingerprint {
+    sources: Vec<HardwareSource>,
+}
+
+impl CompositeFingerprint {
+    pub fn new(sources: impl IntoIterator<Item = HardwareSource>) -> Self {
+        Self {
+            sources: sources.into_iter().collect(),
+        }
+    }
+}
+
+impl Default for CompositeFingerprint {
+    fn default() -> Self {
+        Self::new([
+            HardwareSource::MachineUid,
+            HardwareSource::Arch,
+            HardwareSource::Platform,
+        ])
+    }
+}
+
+impl FingerprintProvider for CompositeFingerprint {
+    fn fingerprint(&self, _storage: &dyn KeygenStorage) -> Result<Option<String>> {
+        let values = self
+            .sources
+            .iter()
+            .map(|source| (source, source.read().unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        if !values
+            .iter()
+            .any(|(source, value)| source.is_machine_specific() && !value.is_empty())
+        {
+            return Ok(None);
+        }
+
+        let values = values
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+
+        // keep each source in its position
+        let mut sha = Sha256::new();
+        sha.update(values.join("|"));
+
+        Ok(Some(format!("{:x}", sha.finalize())))
+    }
+}
@@ -0,0 +1,28 @@
+use super::FingerprintProvider;
+use crate::{
+    storage::{KeygenStorage, StorageEntry},
+    Result,
+};
+
+// random id generated on first run and kept in the plugin storage.
+// survives across machines when the storage does, e.g. portable installs.
+#[derive(Debug, Clone, Copy)]
+pub struct InstallIdFingerprint;
+
+impl FingerprintProvider for InstallIdFingerprint {
+    fn fingerprint(&self, storage: &dyn KeygenStorage) -> Result<Option<String>> {
+        if let Some(install_id) = storage.read(&StorageEntry::InstallId)? {
+            let install_id = install_id.trim();
+
+            if !install_id.is_empty() {
+                return Ok(Some(install_id.to_string()));
+            }
+        }
+
+        let install_id = hex::encode(rand::random::<[u8; 16]>());
+
+        storage.write(&StorageEntry::InstallId, &install_id)?;
+
+        Ok(Some(install_id))
+    }
+}
@@ -0,0 +1,176 @@
+mod hardware;
+mod install_id;
+
+pub use hardware::{CompositeFingerprint, HardwareSource, MachineUidFingerprint};
+pub use install_id::InstallIdFingerprint;
+
+use crate::{
+    storage::{KeygenStorage, StorageEntry},
+    Result,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+pub(crate) type Fingerprint = Arc<dyn FingerprintProvider>;
+
+// identifies this machine to Keygen.
+// `Ok(None)` means the source isn't available here.
+pub trait FingerprintProvider: Send + Sync {
+    fn fingerprint(&self, storage: &dyn KeygenStorage) -> Result<Option<String>>;
+}
+
+// user supplied fingerprint
+pub struct FnFingerprint<F> {
+    f: F,
+}
+
+impl<F> FnFingerprint<F>
+where
+    F: Fn() -> Option<String> + Send + Sync,
+{
+    pub fn new(f: F) -> Self {
+        Self { f }
+    }
+}
+
+impl<F> FingerprintProvider for FnFingerprint<F>
+where
+    F: Fn() -> Option<String> + Send + Sync,
+{
+    fn fingerprint(&self, _storage: &dyn KeygenStorage) -> Result<Option<String>> {
+        Ok((self.f)())
+    }
+}
+
+// the first provider that yields a non-empty fingerprint
+#[derive(Default)]
+pub struct FallbackFingerprint {
+    providers: Vec<Fingerprint>,
+}
+
+impl FallbackFingerprint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn or(mut self, provider: impl FingerprintProvider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+}
+
+impl FingerprintProvider for FallbackFingerprint {
+    fn fingerprint(&self, storage: &dyn KeygenStorage) -> Result<Option<String>> {
+        for provider in &self.providers {
+            match provider.fingerprint(storage) {
+                Ok(Some(fingerprint)) if !fingerprint.trim().is_empty() => {
+                    return Ok(Some(fingerprint))
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    dbg!(err);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// machine-uid, or a persisted install id where it's not available
+// (e.g. containers and minimal linux installs without '/etc/machine-id')
+pub(crate) fn default_fingerprint() -> Fingerprint {
+    Arc::new(
+        FallbackFingerprint::new()
+            .or(MachineUidFingerprint)
+            .or(InstallIdFingerprint),
+    )
+}
+
+// one fingerprint per OS user of this machine
+pub(crate) fn user_scoped(raw: String, user: &str) -> String {
+    if raw.is_empty() {
+        return raw;
+    }
+
+    let mut sha = Sha256::new();
+    sha.update(format!("{}:{}", raw, user));
+
+    format!("{:x}", sha.finalize())
+}
+
+// app scoped HMAC-SHA256 of the raw fingerprint,
+// so the raw machine id never leaves the machine
+pub(crate) fn hash_fingerprint(raw: &str, app_identifier: &str) -> String {
+    // hmac accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(app_identifier.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(raw.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// (hashed fingerprint, raw fingerprint to migrate from).
+// the raw one is kept until the machine is moved to the hashed one.
+pub(crate) fn app_scoped(
+    raw: String,
+    app_identifier: &str,
+    storage: &dyn KeygenStorage,
+) -> (String, Option<String>) {
+    if raw.is_empty() {
+        return (raw, None);
+    }
+
+    let hashed = hash_fingerprint(&raw, app_identifier);
+
+    let read = |entry| {
+        storage.read(&entry).unwrap_or_else(|err| {
+            dbg!(err);
+            None
+        })
+    };
+
+    let migrated = read(StorageEntry::ActivatedFingerprint).as_deref() == Some(hashed.as_str());
+
+    if migrated {
+        return (hashed, None);
+    }
+
+    // only installs from before hashing was enabled need migrating
+    if read(StorageEntry::LicenseKey).is_some() {
+        return (hashed, Some(raw));
+    }
+
+    // fresh install: nothing to migrate, ever
+    if let Err(err) = storage.write(&StorageEntry::ActivatedFingerprint, &hashed) {
+        dbg!(err);
+    }
+
+    (hashed, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_fingerprint;
+
+    // RFC 4231, test case 1
+    #[test]
+    fn hash_fingerprint_rfc4231_case_1() {
+        let key = "\x0b".repeat(20);
+
+        assert_eq!(
+            hash_fingerprint("Hi There", &key),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+    }
+
+    // RFC 4231, test case 2
+    #[test]
+    fn hash_fingerprint_rfc4231_case_2() {
+        assert_eq!(
+            hash_fingerprint("what do ya want for nothing?", "Jefe"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
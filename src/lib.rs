@@ -1,54 +1,206 @@
+mod capabilities;
 mod client;
 mod commands;
 mod err;
+mod events;
+mod fingerprint;
 mod licensed;
 mod machine;
+mod storage;
 
+pub use capabilities::{Config, LicenseCapability};
+pub use client::{sig::SignatureAlgorithm, verify_key::VerifyKey};
+pub use err::{Error, ErrorSummary};
+pub use fingerprint::{
+    CompositeFingerprint, FallbackFingerprint, FingerprintProvider, FnFingerprint, HardwareSource,
+    InstallIdFingerprint, MachineUidFingerprint,
+};
+pub use licensed::{license::License, LicensedState};
+#[cfg(feature = "store")]
+pub use storage::StoreStorage;
+pub use storage::{FsStorage, KeygenStorage, MemoryStorage, StorageEntry};
+
+use capabilities::Capabilities;
+use chrono::{DateTime, Utc};
 use client::KeygenClient;
-use err::Error;
-use licensed::*;
-use machine::Machine;
+use fingerprint::Fingerprint;
+use licensed::VALIDATE_KEY_TIMEOUT;
+use machine::{Machine, MachineMetadata};
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+use storage::Storage;
 use tauri::{
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    Manager, Runtime,
+    AppHandle, Manager, PackageInfo, Runtime,
 };
 use tokio::sync::Mutex;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// guard for your own commands: returns the current License,
+// or returns early with an `ENTITLEMENT_MISSING` ErrorSummary
+// (converted with `.into()`, for commands with their own error type).
+//
+// #[tauri::command]
+// async fn export_pdf(
+//     licensed_state: State<'_, Mutex<LicensedState>>,
+// ) -> Result<(), ErrorSummary> {
+//     let license = require_entitlements!(licensed_state, "PDF_EXPORT");
+//     ...
+// }
+#[macro_export]
+macro_rules! require_entitlements {
+    ($licensed_state:expr, $($code:expr),+ $(,)?) => {
+        match $licensed_state.lock().await.require_entitlements(&[$($code),+]) {
+            Ok(license) => license,
+            Err(err) => return Err($crate::ErrorSummary::from(err).into()),
+        }
+    };
+}
+
+// license checks for the rust side, e.g. in your own commands.
+// sensitive features shouldn't trust the front-end to gate them.
+pub trait KeygenExt<R: Runtime> {
+    // the current licensed state, same as getLicense()
+    fn license(&self) -> impl Future<Output = Option<License>> + Send;
+
+    // whether the current license is valid and has this entitlement
+    fn has_entitlement(&self, code: &str) -> impl Future<Output = bool> + Send;
+
+    // same as the validate_key command: updates the state and caches the response.
+    // doesn't activate the machine.
+    fn validate(
+        &self,
+        key: impl Into<String> + Send,
+        entitlements: Vec<String>,
+    ) -> impl Future<Output = Result<License>> + Send;
+}
+
+impl<R: Runtime, T: Manager<R> + Sync> KeygenExt<R> for T {
+    async fn license(&self) -> Option<License> {
+        let licensed_state = self.state::<Mutex<LicensedState>>();
+        let licensed_state = licensed_state.lock().await;
+
+        licensed_state.get_license()
+    }
+
+    async fn has_entitlement(&self, code: &str) -> bool {
+        self.license()
+            .await
+            .is_some_and(|license| license.has_entitlement(code))
+    }
+
+    async fn validate(
+        &self,
+        key: impl Into<String> + Send,
+        entitlements: Vec<String>,
+    ) -> Result<License> {
+        let app: AppHandle<R> = self.app_handle().clone();
+
+        let storage = self.state::<Storage>();
+        let machine = self.state::<Mutex<Machine>>();
+        let client = self.state::<Mutex<KeygenClient>>();
+        let licensed_state = self.state::<Mutex<LicensedState>>();
+
+        let mut machine = machine.lock().await;
+        let client = client.lock().await;
+
+        let mut licensed_state = licensed_state.lock().await;
+
+        licensed_state
+            .validate_and_update(
+                &app,
+                storage.as_ref(),
+                &mut machine,
+                &client,
+                key.into(),
+                entitlements,
+                true,
+                VALIDATE_KEY_TIMEOUT,
+            )
+            .await
+    }
+}
+
 #[derive(Clone)]
 pub struct Builder {
     pub custom_domain: Option<String>,
     pub api_url: Option<String>,
     pub account_id: Option<String>,
-    pub verify_key: String,
+    pub verify_keys: Vec<VerifyKey>,
+    pub signature_algorithm: SignatureAlgorithm,
     pub version_header: Option<String>,
-    pub cache_lifetime: i64, // in minutes
+    pub max_clock_drift: i64, // in minutes
+    pub cache_lifetime: i64,  // in minutes
+    pub spki_pins: Vec<String>,
+    pub license_key_secret: Option<String>,
+    pub storage: Option<Storage>,
+    pub restore_entitlements: Option<Vec<String>>,
+    pub machine_file_renewal: f64, // fraction of the machine file ttl
+    pub grace_period: i64,         // in days
+    pub fingerprint: Option<Fingerprint>,
+    pub hash_fingerprint: bool,
+    pub migrate_changed_fingerprint: bool,
+    pub machine_metadata: Option<MachineMetadata>,
+    pub user_locked: bool,
+    pub revalidate_interval: i64, // in minutes
+    pub capabilities: Vec<LicenseCapability>,
 }
 
 impl Builder {
-    pub fn new(account_id: impl Into<String>, verify_key: impl Into<String>) -> Self {
+    // the primary verify key, e.g. `VerifyKey::new(CURRENT_KEY, Some(valid_until))`
+    // when it's about to be rotated
+    pub fn new(account_id: impl Into<String>, verify_key: impl Into<VerifyKey>) -> Self {
         Self {
             custom_domain: None,
             api_url: Some("https://api.keygen.sh".into()),
             account_id: Some(account_id.into()),
-            verify_key: verify_key.into(),
+            verify_keys: vec![verify_key.into()],
+            signature_algorithm: SignatureAlgorithm::Ed25519,
             version_header: None,
+            max_clock_drift: 5,
             cache_lifetime: 240,
+            spki_pins: vec![],
+            license_key_secret: None,
+            storage: None,
+            restore_entitlements: None,
+            machine_file_renewal: 0.0,
+            grace_period: 0,
+            fingerprint: None,
+            hash_fingerprint: false,
+            migrate_changed_fingerprint: false,
+            machine_metadata: None,
+            user_locked: false,
+            revalidate_interval: 0,
+            capabilities: vec![],
         }
     }
 
     pub fn with_custom_domain(
         custom_domain: impl Into<String>,
-        verify_key: impl Into<String>,
+        verify_key: impl Into<VerifyKey>,
     ) -> Self {
         Self {
             custom_domain: Some(custom_domain.into()),
             account_id: None,
             api_url: None,
-            verify_key: verify_key.into(),
+            verify_keys: vec![verify_key.into()],
+            signature_algorithm: SignatureAlgorithm::Ed25519,
             version_header: None,
+            max_clock_drift: 5,
             cache_lifetime: 240,
+            spki_pins: vec![],
+            license_key_secret: None,
+            storage: None,
+            restore_entitlements: None,
+            machine_file_renewal: 0.0,
+            grace_period: 0,
+            fingerprint: None,
+            hash_fingerprint: false,
+            migrate_changed_fingerprint: false,
+            machine_metadata: None,
+            user_locked: false,
+            revalidate_interval: 0,
+            capabilities: vec![],
         }
     }
 
@@ -59,49 +211,302 @@ impl Builder {
         self
     }
 
+    // additional verify key, tried in order after the previous ones.
+    // useful for key rotation: the key is ignored after `valid_until`.
+    pub fn verify_key(
+        mut self,
+        verify_key: impl Into<String>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> Self {
+        self.verify_keys
+            .push(VerifyKey::new(verify_key, valid_until));
+        self
+    }
+
+    // rsa algorithms need the account's RSA public key (PEM) as a verify key
+    pub fn signature_algorithm(mut self, signature_algorithm: SignatureAlgorithm) -> Self {
+        self.signature_algorithm = signature_algorithm;
+        self
+    }
+
     pub fn version_header(mut self, version_header: impl Into<String>) -> Self {
         self.version_header = Some(version_header.into());
         self
     }
 
+    // a negative value disables the response date check
+    pub fn max_clock_drift(mut self, max_clock_drift: i64) -> Self {
+        self.max_clock_drift = max_clock_drift;
+        self
+    }
+
     pub fn cache_lifetime(mut self, cache_lifetime: i64) -> Self {
         self.cache_lifetime = cache_lifetime.clamp(60, 1440);
         self
     }
 
-    pub fn build<R: Runtime>(self) -> TauriPlugin<R> {
-        PluginBuilder::new("keygen")
+    // base64 encoded sha256 digests of the Keygen API's SubjectPublicKeyInfo.
+    // requests fail with TLS_PIN_MISMATCH unless a certificate in the chain matches a pin.
+    pub fn spki_pins<I, S>(mut self, spki_pins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.spki_pins = spki_pins.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // app specific secret for encrypting the cached license key.
+    // defaults to the app identifier.
+    pub fn license_key_secret(mut self, license_key_secret: impl Into<String>) -> Self {
+        self.license_key_secret = Some(license_key_secret.into());
+        self
+    }
+
+    // where to keep the license key, machine file and validation cache.
+    // defaults to files under `[APP_DATA]/keygen`.
+    pub fn storage(mut self, storage: impl KeygenStorage + 'static) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    // entitlement scope of the validation cache to restore on startup.
+    // defaults to the most recent valid cache, whatever its scope.
+    pub fn restore_entitlements<I, S>(mut self, entitlements: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.restore_entitlements = Some(entitlements.into_iter().map(Into::into).collect());
+        self
+    }
+
+    // renew the machine file in the background once less than this
+    // fraction of its ttl is left, e.g. 0.25. 0 (the default) disables renewal.
+    pub fn machine_file_renewal(mut self, machine_file_renewal: f64) -> Self {
+        self.machine_file_renewal = machine_file_renewal.clamp(0.0, 1.0);
+        self
+    }
+
+    // keep a license usable offline for this many days
+    // since its last successful validation. 0 disables it.
+    pub fn grace_period(mut self, grace_period: i64) -> Self {
+        self.grace_period = grace_period.max(0);
+        self
+    }
+
+    // how this machine is identified.
+    // defaults to machine-uid, falling back to a persisted install id.
+    pub fn fingerprint(mut self, fingerprint: impl FingerprintProvider + 'static) -> Self {
+        self.fingerprint = Some(Arc::new(fingerprint));
+        self
+    }
+
+    // send an HMAC of the fingerprint, keyed by the app identifier, instead of the raw id.
+    // existing activations are moved to the hashed fingerprint on the next validation.
+    pub fn hash_fingerprint(mut self, hash_fingerprint: bool) -> Self {
+        self.hash_fingerprint = hash_fingerprint;
+        self
+    }
+
+    // when the machine id changed since the last activation,
+    // move the stale machine's activation to this one on validation
+    pub fn migrate_changed_fingerprint(mut self, migrate_changed_fingerprint: bool) -> Self {
+        self.migrate_changed_fingerprint = migrate_changed_fingerprint;
+        self
+    }
+
+    // metadata sent on machine activation and updateMachine(),
+    // e.g. app version and install channel
+    pub fn machine_metadata<F>(mut self, machine_metadata: F) -> Self
+    where
+        F: Fn(&PackageInfo) -> serde_json::Map<String, serde_json::Value> + Send + Sync + 'static,
+    {
+        self.machine_metadata = Some(Arc::new(machine_metadata));
+        self
+    }
+
+    // one seat per OS user, instead of per machine:
+    // the username is folded into the fingerprint and machine name,
+    // and the default storage is kept per user.
+    pub fn user_locked(mut self, user_locked: bool) -> Self {
+        self.user_locked = user_locked;
+        self
+    }
+
+    // revalidate the license in the background every this many minutes,
+    // with the last requested entitlements. 0 disables it.
+    pub fn revalidate_interval(mut self, revalidate_interval: i64) -> Self {
+        self.revalidate_interval = match revalidate_interval {
+            ..=0 => 0,
+            minutes => minutes.max(5),
+        };
+        self
+    }
+
+    // grant a capability (json or toml) to valid licenses with this entitlement,
+    // e.g. `include_str!("../runtime-capabilities/pro.json")`
+    pub fn entitlement_capability(
+        mut self,
+        entitlement: impl Into<String>,
+        capability: impl Into<String>,
+    ) -> Self {
+        self.capabilities.push(LicenseCapability::entitlement(
+            entitlement.into(),
+            capability.into(),
+        ));
+        self
+    }
+
+    // grant a capability (json or toml) to valid licenses of this policy
+    pub fn policy_capability(
+        mut self,
+        policy_id: impl Into<String>,
+        capability: impl Into<String>,
+    ) -> Self {
+        self.capabilities.push(LicenseCapability::policy(
+            policy_id.into(),
+            capability.into(),
+        ));
+        self
+    }
+
+    pub fn build<R: Runtime>(self) -> TauriPlugin<R, Option<Config>> {
+        PluginBuilder::<R, Option<Config>>::new("keygen")
             .invoke_handler(tauri::generate_handler![
                 commands::get_license,
                 commands::get_license_key,
                 commands::validate_key,
                 commands::activate,
                 commands::checkout_machine,
+                commands::update_machine,
                 commands::reset_license,
                 commands::reset_license_key,
             ])
-            .setup(move |app, _api| {
+            .setup(move |app, api| {
                 // get app info
                 let app_name = app.package_info().name.clone();
                 let app_version = app.package_info().version.to_string();
 
+                let storage_secret = self
+                    .license_key_secret
+                    .unwrap_or_else(|| app.config().identifier.clone());
+
+                // OS user, in user locked mode
+                let user = self.user_locked.then(whoami::username);
+
+                // init storage
+                let storage: Storage = match self.storage {
+                    Some(storage) => storage,
+                    None => {
+                        let Ok(data_dir) = app.path().app_data_dir() else {
+                            return Err(Error::PathErr("Can't resolve app data dir".into()).into());
+                        };
+
+                        // not roaming on windows: what this machine was activated with
+                        let Ok(local_data_dir) = app.path().app_local_data_dir() else {
+                            return Err(
+                                Error::PathErr("Can't resolve app local data dir".into()).into()
+                            );
+                        };
+
+                        let keygen_dir = |dir: PathBuf| match &user {
+                            Some(user) => dir
+                                .join("keygen")
+                                .join("users")
+                                .join(storage::user_dir_name(user)),
+                            None => dir.join("keygen"),
+                        };
+
+                        Arc::new(
+                            FsStorage::new(keygen_dir(data_dir))
+                                .local_dir(keygen_dir(local_data_dir)),
+                        )
+                    }
+                };
+
+                // get fingerprint
+                let fingerprint = self
+                    .fingerprint
+                    .unwrap_or_else(fingerprint::default_fingerprint)
+                    .fingerprint(storage.as_ref())
+                    .unwrap_or_else(|err| {
+                        dbg!(err);
+                        None
+                    })
+                    .unwrap_or_default();
+
+                if fingerprint.is_empty() {
+                    dbg!("Failed getting machine fingerprint");
+                }
+
+                // user scoped fingerprint
+                let fingerprint = match &user {
+                    Some(user) => fingerprint::user_scoped(fingerprint, user),
+                    None => fingerprint,
+                };
+
+                // app scoped fingerprint
+                let (fingerprint, legacy_fingerprint) = if self.hash_fingerprint {
+                    fingerprint::app_scoped(fingerprint, &app.config().identifier, storage.as_ref())
+                } else {
+                    (fingerprint, None)
+                };
+
                 // init machine
-                let machine = Machine::new(app_name, app_version);
+                let metadata = self
+                    .machine_metadata
+                    .map(|machine_metadata| machine_metadata(app.package_info()))
+                    .unwrap_or_default();
+
+                let mut machine = Machine::new(
+                    fingerprint,
+                    legacy_fingerprint,
+                    app_name,
+                    app_version,
+                    storage_secret,
+                    metadata,
+                    user,
+                );
+
+                // detect a changed machine id
+                if self.migrate_changed_fingerprint {
+                    if let Err(err) = machine.load_previous_fingerprint(storage.as_ref()) {
+                        dbg!(err);
+                    }
+                }
 
                 // init keygen client
                 let keygen_client = KeygenClient::new(
                     self.custom_domain,
                     self.api_url,
                     self.account_id,
-                    self.verify_key,
+                    self.verify_keys,
+                    self.signature_algorithm,
                     self.version_header,
+                    self.max_clock_drift,
                     self.cache_lifetime,
+                    self.grace_period,
+                    self.spki_pins,
                     machine.user_agent.clone(),
-                );
+                    storage.clone(),
+                )?;
+
+                // entitlement and policy capabilities from `plugins.keygen`,
+                // overridden by the Builder's
+                app.manage(Capabilities::new(api.config().as_ref(), self.capabilities));
 
                 // init state
-                match LicensedState::load(app, &keygen_client, &machine) {
+                match LicensedState::load(
+                    storage.as_ref(),
+                    &keygen_client,
+                    &machine,
+                    self.restore_entitlements.as_ref(),
+                ) {
                     Ok(licensed_state) => {
+                        // capabilities of the restored license
+                        capabilities::grant(app, licensed_state.get_license().as_ref());
+
                         app.manage(Mutex::new(licensed_state));
                     }
                     Err(err) => {
@@ -111,6 +516,18 @@ impl Builder {
                 }
                 app.manage(Mutex::new(machine));
                 app.manage(Mutex::new(keygen_client));
+                app.manage(storage);
+
+                // keep the machine file fresh, and follow its expiry
+                machine::renewal::spawn(app.clone(), self.machine_file_renewal);
+
+                // pick up licenses suspended or revoked on Keygen
+                if self.revalidate_interval > 0 {
+                    licensed::revalidation::spawn(
+                        app.clone(),
+                        Duration::from_secs(self.revalidate_interval as u64 * 60),
+                    );
+                }
 
                 Ok(())
             })
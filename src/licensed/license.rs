@@ -7,7 +7,7 @@ use crate::{
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct License {
     #[serde(skip_serializing)]
@@ -20,19 +20,29 @@ pub struct License {
     pub entitlements: Vec<String>,
     pub metadata: serde_json::Value,
     pub valid: bool,
+    pub grace_remaining: Option<i64>, // in seconds
 }
 
 impl License {
+    // entitlements only count on a valid license
+    pub fn has_entitlement(&self, code: &str) -> bool {
+        self.valid
+            && self
+                .entitlements
+                .iter()
+                .any(|entitlement| entitlement == code)
+    }
+
     pub(crate) fn should_maintain_access(&self) -> bool {
         self.valid && self.code == "EXPIRED"
     }
 
-    pub(crate) fn has_expired(&self) -> bool {
+    pub(crate) fn has_expired(&self, now: DateTime<Utc>) -> bool {
         self.expiry
             .clone()
             .and_then(|expiry| DateTime::parse_from_rfc3339(&expiry).ok())
             .map_or(true, |expiry_date| {
-                expiry_date.signed_duration_since(Utc::now()).num_minutes() <= 0
+                expiry_date.signed_duration_since(now).num_minutes() <= 0
             })
     }
 
@@ -46,11 +56,7 @@ impl License {
                 let entitlements: Vec<String> = lic_res
                     .meta
                     .scope
-                    .and_then(|scope| {
-                        scope.get("entitlements").and_then(|entitlements_val| {
-                            serde_json::from_value::<Vec<String>>(entitlements_val.clone()).ok()
-                        })
-                    })
+                    .and_then(|scope| scope.entitlements)
                     .unwrap_or_default();
 
                 Some(Self {
@@ -63,16 +69,20 @@ impl License {
                     metadata: lic_data.attributes.metadata,
                     entitlements,
                     valid: lic_res.meta.valid,
+                    grace_remaining: None,
                 })
             }
             None => None,
         }
     }
 
-    pub(crate) fn from_machine_license(machine_license: MachineLicense) -> Result<Option<Self>> {
+    pub(crate) fn from_machine_license(
+        machine_license: MachineLicense,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Self>> {
         // if machine file expiry exists, check validity
         if let Some(expiry) = machine_license.meta.expiry {
-            if Self::has_machine_file_expired(machine_license.meta.issued, expiry)? {
+            if Self::has_machine_file_expired(machine_license.meta.issued, expiry, now)? {
                 return Ok(None);
             }
         }
@@ -107,13 +117,17 @@ impl License {
                     entitlements: entitlement_codes,
                     metadata: included_lic.attributes.metadata,
                     valid: true,
+                    grace_remaining: None,
                 }
             })
             .map_or(Ok(None), |license| Ok(Some(license)))
     }
 
-    fn has_machine_file_expired(issued: String, expiry: String) -> Result<bool> {
-        let now = Utc::now();
+    fn has_machine_file_expired(
+        issued: String,
+        expiry: String,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
         let issued = DateTime::parse_from_rfc3339(&issued)
             .map_err(|_| Error::ParseErr("Failed parsing machine file issued date".into()))?;
         let expiry = DateTime::parse_from_rfc3339(&expiry)
@@ -1,89 +1,221 @@
 pub mod license;
+pub mod revalidation;
 pub mod types;
 
 use crate::{
     client::{KeygenClient, KeygenResponseCache},
-    err::{parse_err_json, Error},
+    err::Error,
+    events,
     machine::Machine,
+    storage::{KeygenStorage, StorageEntry},
     Result,
 };
-use chrono::Local;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use chrono::{DateTime, FixedOffset};
 use license::*;
+use rand::Rng;
 use reqwest::{Method, StatusCode};
 use serde::Serialize;
 use sha2::{Digest, Sha256};
-use std::{
-    fs::{self, File},
-    io::Write,
-    path::PathBuf,
-    time::Duration,
-};
-use tauri::{AppHandle, Manager, Runtime};
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
 use types::*;
 
+static LICENSE_KEY_ENC_PREFIX: &str = "aes-256-gcm:";
+
+// validation requests made on the user's behalf, e.g. validateKey()
+pub(crate) static VALIDATE_KEY_TIMEOUT: Duration = Duration::from_secs(90);
+
 #[derive(Debug, Serialize, Default, Clone)]
 pub struct LicensedState {
     license: Option<License>,
+    // the last validation request, repeated by the background revalidation
+    #[serde(skip)]
+    entitlements: Vec<String>,
+    #[serde(skip)]
+    cache_valid_response: bool,
+    // loaded from the machine file: only valid until it expires
+    #[serde(skip)]
+    from_machine_file: bool,
 }
 
 impl LicensedState {
-    pub(crate) fn load<R: Runtime>(
-        app: &AppHandle<R>,
+    pub(crate) fn load(
+        storage: &dyn KeygenStorage,
         client: &KeygenClient,
         machine: &Machine,
+        entitlements: Option<&Vec<String>>,
     ) -> Result<Self> {
-        if let Some(key) = Self::get_cached_license_key(app)? {
+        let mut state = Self {
+            license: None,
+            entitlements: entitlements.cloned().unwrap_or_default(),
+            cache_valid_response: true,
+            from_machine_file: false,
+        };
+
+        if let Some(key) = Self::get_cached_license_key(storage, machine)? {
             // load from machine file
-            match machine.load_machine_file(&key, client, app) {
-                Ok(Some(machine_license)) => match License::from_machine_license(machine_license) {
-                    Ok(license) => return Ok(Self { license }),
-                    Err(e) => {
-                        dbg!(e);
+            match machine.load_machine_file(&key, client, storage) {
+                Ok(Some(machine_license)) => {
+                    match License::from_machine_license(machine_license, client.now()) {
+                        Ok(license) => {
+                            state.license = license;
+                            state.cache_valid_response = false;
+                            state.from_machine_file = state.license.is_some();
+                            return Ok(state);
+                        }
+                        Err(e) => {
+                            dbg!(e);
+                        }
                     }
-                },
+                }
                 Ok(None) => {}
                 Err(e) => {
                     dbg!(e);
                 }
             }
 
-            // load today's response cache
-            if let Some((res_cache, cache_path)) = Self::get_response_cache(app, &key)? {
-                // house keeping: delete yesterdays' cache
-                match Self::clear_response_cache_except(app, cache_path) {
-                    Ok(()) => {}
+            // house keeping: delete expired caches
+            Self::clear_expired_response_cache(storage, client);
+
+            // load the requested entitlement scope, or the latest valid one
+            let lic_res = match entitlements {
+                Some(entitlements) => {
+                    match Self::load_response_cache(storage, client, machine, &key, entitlements) {
+                        Ok(lic_res) => lic_res,
+                        Err(err) => {
+                            dbg!(err);
+                            None
+                        }
+                    }
+                }
+                None => Self::load_latest_response_cache(storage, client, machine, &key),
+            };
+
+            if let Some(lic_res) = lic_res {
+                let scoped_entitlements = lic_res
+                    .meta
+                    .scope
+                    .as_ref()
+                    .and_then(|scope| scope.entitlements.clone());
+
+                if let Some(license) = Self::license_from_validation(lic_res, client) {
+                    state.license = Some(license);
+                    if let Some(entitlements) = scoped_entitlements {
+                        state.entitlements = entitlements;
+                    }
+                    return Ok(state);
+                }
+            }
+
+            // offline, within the grace period since the last successful validation
+            if client.grace_period_enabled() {
+                match Self::load_grace_license(storage, client, machine, &key) {
+                    Ok(Some(license)) => {
+                        state.license = Some(license);
+                        return Ok(state);
+                    }
+                    Ok(None) => {}
                     Err(err) => {
                         dbg!(err);
                     }
-                };
-
-                // verify and parse today's cache
-                let lic_res = client.verify_response_cache(res_cache)?;
-                if let Some(license) = License::from_license_response(lic_res) {
-                    return Ok(Self {
-                        license: Some(license),
-                    });
                 }
             }
         }
 
-        Ok(Self { license: None })
+        Ok(state)
     }
 
-    pub(crate) fn update(&mut self, license: Option<License>) {
+    // returns whether the license changed.
+    // graceRemaining counts down between validations, so it's not a change on its own.
+    pub(crate) fn update(&mut self, license: Option<License>) -> bool {
+        let comparable = |license: &Option<License>| {
+            let mut license = license.clone();
+            if let Some(license) = license.as_mut() {
+                license.grace_remaining = None;
+            }
+            serde_json::to_value(license).ok()
+        };
+
+        let changed = comparable(&self.license) != comparable(&license);
+
         self.license = license;
+        self.from_machine_file = false;
+
+        changed
     }
 
-    pub(crate) fn get_license(&self) -> Option<License> {
+    pub fn get_license(&self) -> Option<License> {
         self.license.clone()
     }
 
+    pub(crate) fn is_from_machine_file(&self) -> bool {
+        self.from_machine_file
+    }
+
+    // the machine file this state was loaded from expired:
+    // fall back to the validation cache or the grace period, like on startup
+    pub(crate) fn reload<R: Runtime>(
+        &mut self,
+        app: &AppHandle<R>,
+        storage: &dyn KeygenStorage,
+        client: &KeygenClient,
+        machine: &Machine,
+    ) {
+        let entitlements = Some(&self.entitlements).filter(|codes| !codes.is_empty());
+
+        let state = match Self::load(storage, client, machine, entitlements) {
+            Ok(state) => state,
+            Err(err) => {
+                dbg!(err);
+                Self::default()
+            }
+        };
+
+        events::update_license(app, self, state.license, client.now());
+
+        self.entitlements = state.entitlements;
+        self.cache_valid_response = state.cache_valid_response;
+        self.from_machine_file = state.from_machine_file;
+    }
+
+    // the current license, when it's valid and has all of these entitlements
+    pub fn require_entitlements(&self, codes: &[&str]) -> Result<License> {
+        let license = match &self.license {
+            Some(license) if license.valid => license,
+            _ => {
+                return Err(Error::LicenseErr {
+                    code: "ENTITLEMENT_MISSING".into(),
+                    detail: "No valid license".into(),
+                })
+            }
+        };
+
+        let missing = codes
+            .iter()
+            .filter(|code| !license.has_entitlement(code))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(Error::LicenseErr {
+                code: "ENTITLEMENT_MISSING".into(),
+                detail: format!("License is missing entitlements: {}", missing.join(", ")),
+            });
+        }
+
+        Ok(license.clone())
+    }
+
     pub(crate) async fn validate_key(
         &mut self,
         key: String,
         entitlements: Vec<String>,
         machine: &Machine,
         client: &KeygenClient,
+        timeout: Duration,
     ) -> Result<(License, KeygenResponseCache)> {
         // make sure fingerprint is not an empty string
         if machine.fingerprint.is_empty() {
@@ -96,25 +228,38 @@ impl LicensedState {
         }
 
         // prepare request
-        let url = client.build_url("licenses/actions/validate-key".into(), None)?;
+        let url = client.validate_key_url()?;
 
-        let mut body = serde_json::json!({
+        // random nonce, to be echoed back in the signed response.
+        // kept within js' safe integer range.
+        let nonce: u64 = rand::thread_rng().gen_range(1..=9_007_199_254_740_991);
+
+        // not migrated yet: the machine may still be activated under the raw fingerprint
+        let mut scope = match &machine.legacy_fingerprint {
+            Some(legacy_fingerprint) => serde_json::json!({
+                "fingerprints": [machine.fingerprint.clone(), legacy_fingerprint.clone()]
+            }),
+            None => serde_json::json!({
+                "fingerprint": machine.fingerprint.clone()
+            }),
+        };
+
+        if !entitlements.is_empty() {
+            scope["entitlements"] = serde_json::json!(entitlements);
+        }
+
+        let body = serde_json::json!({
             "meta": {
+                "nonce": nonce,
                 "key": key.trim_end(),
-                "scope": {
-                    "fingerprint": machine.fingerprint.clone()
-                }
+                "scope": scope
             }
         });
 
-        if !entitlements.is_empty() {
-            body["meta"]["scope"]["entitlements"] = serde_json::json!(entitlements);
-        }
-
         // request validation
         let response = client
             .post(url.to_string())
-            .timeout(Duration::from_secs(90))
+            .timeout(timeout)
             .header("Content-Type", "application/vnd.api+json")
             .header("Accept", "application/vnd.api+json")
             .json(&body)
@@ -129,7 +274,12 @@ impl LicensedState {
         match res_status {
             StatusCode::OK => {
                 // verify signature
-                match client.verify_response(Method::POST.to_string(), url, res_headers, res_text) {
+                match client.verify_nonce_response(
+                    Method::POST.to_string(),
+                    url,
+                    res_headers,
+                    res_text,
+                ) {
                     Ok(res_cache) => {
                         // map res_json
                         let lic_res: LicenseResponse =
@@ -139,6 +289,11 @@ impl LicensedState {
                                 )
                             })?;
 
+                        // prevent replay attack
+                        if lic_res.meta.nonce != Some(nonce) {
+                            return Err(Error::BadResponse("Nonce didn't match".into()));
+                        }
+
                         // not found: no data
                         if lic_res.meta.code == "NOT_FOUND" {
                             return Err(Error::LicenseErr {
@@ -147,174 +302,848 @@ impl LicensedState {
                             });
                         }
 
+                        // response must be scoped to what was requested
+                        if !Self::scope_matches(&lic_res, &machine.fingerprint, Some(&entitlements))
+                        {
+                            return Err(Error::BadResponse("Validation scope didn't match".into()));
+                        }
+
+                        // a fresh response: correct the clock before the expiry math
+                        client.apply_clock_skew(&res_cache)?;
+
                         // license response should have data here
                         // but just to be safe..
-                        let license = License::from_license_response(lic_res).ok_or_else(|| {
-                            Error::BadResponse("Missing data on license validation response".into())
-                        })?;
+                        let license =
+                            Self::license_from_validation(lic_res, client).ok_or_else(|| {
+                                Error::BadResponse(
+                                    "Missing data on license validation response".into(),
+                                )
+                            })?;
 
                         Ok((license, res_cache))
                     }
                     Err(err) => Err(err),
                 }
             }
-            status_code => Err(parse_err_json(status_code, res_json)),
+            status_code => Err(client.verify_err_response(
+                Method::POST.to_string(),
+                url,
+                status_code,
+                res_headers,
+                res_text,
+                res_json,
+            )),
         }
     }
 
-    pub(crate) fn cache_license_key<R: Runtime>(key: &String, app: &AppHandle<R>) -> Result<()> {
-        let path = Self::get_license_key_cache_path(app)?;
+    // validate, then update the state, caches and machine activation with the result
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn validate_and_update<R: Runtime>(
+        &mut self,
+        app: &AppHandle<R>,
+        storage: &dyn KeygenStorage,
+        machine: &mut Machine,
+        client: &KeygenClient,
+        key: String,
+        entitlements: Vec<String>,
+        cache_valid_response: bool,
+        timeout: Duration,
+    ) -> Result<License> {
+        let mut result = self
+            .validate_key(key.clone(), entitlements.clone(), machine, client, timeout)
+            .await;
+
+        // the machine id changed since the last activation:
+        // move the stale machine's activation to this one, then validate again
+        let mut migrated = false;
+
+        if let Ok((license, _)) = &result {
+            let license = license.clone();
+
+            match machine
+                .migrate_changed_fingerprint(&license, client, storage)
+                .await
+            {
+                Ok(true) => {
+                    migrated = true;
+                    result = self
+                        .validate_key(key.clone(), entitlements.clone(), machine, client, timeout)
+                        .await;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    dbg!(err);
+                }
+            }
+        }
+
+        let (license, res_cache) = match result {
+            Ok(result) => result,
+            // the current license was deleted on Keygen, e.g. revoked.
+            // a mistyped key doesn't reset it.
+            Err(Error::LicenseErr { code, detail })
+                if code == "NOT_FOUND"
+                    && self
+                        .license
+                        .as_ref()
+                        .is_some_and(|license| license.key == key.trim_end()) =>
+            {
+                events::update_license(app, self, None, client.now());
 
-        let mut f = File::create(path)?;
-        f.write_all(key.as_bytes())?;
+                if let Err(err) = Self::remove_offline_licenses(storage) {
+                    dbg!(err);
+                }
 
-        Ok(())
+                return Err(Error::LicenseErr { code, detail });
+            }
+            Err(err) => return Err(err),
+        };
+
+        // start of the offline grace period
+        if client.grace_period_enabled() {
+            if let Err(err) = Self::record_validation(storage, &license, &res_cache) {
+                dbg!(err);
+            }
+        }
+
+        // e.g. suspended: don't restore the last valid response on the next startup
+        if !license.valid {
+            let entry = StorageEntry::ValidationCache(Self::get_response_cache_name(
+                &license.key,
+                &machine.fingerprint,
+                &entitlements,
+            ));
+
+            if let Err(err) = storage
+                .remove(&entry)
+                .and_then(|_| Self::remove_validation_record(storage))
+            {
+                dbg!(err);
+            }
+        }
+
+        // cache valid response
+        if license.valid && cache_valid_response && license.expiry.is_some() {
+            Self::cache_response(
+                storage,
+                &license.key,
+                &machine.fingerprint,
+                &entitlements,
+                res_cache,
+            )?;
+        }
+
+        // update state, and remember the request for the background revalidation
+        self.entitlements = entitlements;
+        self.cache_valid_response = cache_valid_response;
+
+        events::update_license(app, self, Some(license.clone()), client.now());
+
+        // cache license key
+        Self::cache_license_key(&license.key, storage, machine)?;
+
+        // the machine file is encrypted for the stale fingerprint and can't be read here:
+        // check out a new one now that this machine holds the activation
+        if migrated {
+            if let Err(err) = machine.rekey_machine_file(self, client, storage).await {
+                dbg!(err);
+            }
+        }
+
+        // re-activate under the hashed fingerprint
+        if license.valid && machine.legacy_fingerprint.is_some() {
+            if let Err(err) = machine.migrate_fingerprint(&license, client, storage).await {
+                dbg!(err);
+            }
+        }
+
+        // a valid license means this machine is activated:
+        // keep its attributes current after an app update
+        if license.valid {
+            if let Err(err) = machine.record_validated_fingerprint(storage) {
+                dbg!(err);
+            }
+
+            if let Err(err) = machine
+                .update_if_version_changed(self, client, storage)
+                .await
+            {
+                dbg!(err);
+            }
+        }
+
+        Ok(license)
     }
 
-    pub(crate) fn get_cached_license_key<R: Runtime>(app: &AppHandle<R>) -> Result<Option<String>> {
-        let path = Self::get_license_key_cache_path(app)?;
+    // valid licenses get the grace period left since their validation
+    fn license_from_validation(lic_res: LicenseResponse, client: &KeygenClient) -> Option<License> {
+        let validated_at = lic_res.meta.ts.clone();
 
-        // no license key
-        if !path.exists() {
+        let mut license = License::from_license_response(lic_res)?;
+
+        if license.valid {
+            license.grace_remaining = client.grace_remaining(&validated_at);
+        }
+
+        Some(license)
+    }
+
+    fn load_grace_license(
+        storage: &dyn KeygenStorage,
+        client: &KeygenClient,
+        machine: &Machine,
+        license_key: &String,
+    ) -> Result<Option<License>> {
+        let Some(record) = Self::get_response_cache(storage, &StorageEntry::LastValidation)? else {
+            return Ok(None);
+        };
+
+        // signed by Keygen: can't be forged, nor its timestamp moved
+        let lic_res = client.verify_validation_record(record)?;
+
+        if !Self::scope_matches(&lic_res, &machine.fingerprint, None) {
+            return Err(Error::BadCache("Validation scope didn't match".into()));
+        }
+
+        let Some(mut license) = Self::license_from_validation(lic_res, client) else {
+            return Ok(None);
+        };
+
+        if &license.key != license_key {
+            return Err(Error::BadCache(
+                "Last validated license key didn't match".into(),
+            ));
+        }
+
+        // grace period is over
+        if !license.valid || license.grace_remaining.unwrap_or_default() <= 0 {
             return Ok(None);
         }
 
-        let key = fs::read_to_string(path)?;
+        // license expired while offline
+        if license.expiry.is_some()
+            && license.has_expired(client.now())
+            && !license.should_maintain_access()
+        {
+            return Ok(None);
+        }
+
+        license.code = "GRACE_PERIOD".into();
+        license.detail = "is offline, within the grace period".into();
+
+        Ok(Some(license))
+    }
+
+    // the last validation response, to start the grace period from.
+    // an invalid response ends the grace period.
+    pub(crate) fn record_validation(
+        storage: &dyn KeygenStorage,
+        license: &License,
+        res_cache: &KeygenResponseCache,
+    ) -> Result<()> {
+        if !license.valid {
+            return Self::remove_validation_record(storage);
+        }
+
+        let record = serde_json::to_string(res_cache)
+            .map_err(|_| Error::ParseErr("Failed parsing validation record to text".into()))?;
+
+        storage.write(&StorageEntry::LastValidation, &record)
+    }
+
+    pub(crate) fn remove_validation_record(storage: &dyn KeygenStorage) -> Result<()> {
+        storage.remove(&StorageEntry::LastValidation)
+    }
 
-        Ok(Some(key))
+    // machine file, validation caches and grace period record
+    pub(crate) fn remove_offline_licenses(storage: &dyn KeygenStorage) -> Result<()> {
+        Machine::remove_machine_file(storage)?;
+        Self::clear_response_cache(storage)?;
+        Self::remove_validation_record(storage)
     }
 
-    pub(crate) fn remove_cached_license_key<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
-        let path = Self::get_license_key_cache_path(app)?;
+    fn scope_matches(
+        lic_res: &LicenseResponse,
+        fingerprint: &String,
+        entitlements: Option<&Vec<String>>,
+    ) -> bool {
+        let Some(scope) = &lic_res.meta.scope else {
+            return false;
+        };
+
+        // single fingerprint, or the hashed one amongst the migration fingerprints
+        let fingerprint_matches = scope.fingerprint.as_ref() == Some(fingerprint)
+            || scope
+                .fingerprints
+                .as_ref()
+                .is_some_and(|fingerprints| fingerprints.contains(fingerprint));
 
-        if path.exists() {
-            fs::remove_file(&path)?;
+        if !fingerprint_matches {
+            return false;
         }
 
-        Ok(())
+        // compare as sets
+        if let Some(entitlements) = entitlements {
+            let mut requested = entitlements.clone();
+            requested.sort();
+            requested.dedup();
+
+            let mut scoped = scope.entitlements.clone().unwrap_or_default();
+            scoped.sort();
+            scoped.dedup();
+
+            if requested != scoped {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub(crate) fn cache_license_key(
+        key: &str,
+        storage: &dyn KeygenStorage,
+        machine: &Machine,
+    ) -> Result<()> {
+        let enc = Self::encrypt_license_key(key, machine)?;
+
+        storage.write(&StorageEntry::LicenseKey, &enc)
     }
 
-    fn get_license_key_cache_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
-        // get app data dir
-        let Ok(data_dir) = app.path().app_data_dir() else {
-            return Err(Error::PathErr("Can't resolve app data dir".into()));
+    pub(crate) fn get_cached_license_key(
+        storage: &dyn KeygenStorage,
+        machine: &Machine,
+    ) -> Result<Option<String>> {
+        // no license key
+        let Some(content) = storage.read(&StorageEntry::LicenseKey)? else {
+            return Ok(None);
+        };
+
+        // plaintext key from earlier versions: encrypt it
+        let Some(enc) = content.strip_prefix(LICENSE_KEY_ENC_PREFIX) else {
+            Self::cache_license_key(&content, storage, machine)?;
+            return Ok(Some(content));
         };
-        // get cache dir
-        let keygen_cache_dir = data_dir.join("keygen");
 
-        if !keygen_cache_dir.exists() {
-            fs::create_dir_all(&keygen_cache_dir)?;
+        // e.g. the key was cached on another machine,
+        // or the file is corrupt
+        match Self::decrypt_license_key(enc, &machine.storage_key()) {
+            Ok(key) => Ok(Some(key)),
+            Err(err) => {
+                // cached under the raw fingerprint, before hashing: re-encrypt it
+                for fallback_key in machine.fallback_storage_keys() {
+                    if let Ok(key) = Self::decrypt_license_key(enc, &fallback_key) {
+                        Self::cache_license_key(&key, storage, machine)?;
+                        return Ok(Some(key));
+                    }
+                }
+
+                dbg!(err);
+                Ok(None)
+            }
         }
+    }
+
+    // format: "aes-256-gcm:{ciphertext}.{nonce}.{tag}"
+    fn encrypt_license_key(key: &str, machine: &Machine) -> Result<String> {
+        let storage_key = machine.storage_key();
+        let aes = Aes256Gcm::new(Key::from_slice(&storage_key));
 
-        // dir path
-        let dir_path = keygen_cache_dir.join("key");
+        let nonce: [u8; 12] = rand::random();
 
-        Ok(dir_path)
+        let mut ciphertext = aes
+            .encrypt(Nonce::from_slice(&nonce), key.as_bytes())
+            .map_err(|_| Error::ParseErr("Failed encrypting license key".into()))?;
+
+        // aes-gcm appends the 16 bytes authentication tag
+        let tag = ciphertext.split_off(ciphertext.len() - 16);
+
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        Ok(format!(
+            "{}{}.{}.{}",
+            LICENSE_KEY_ENC_PREFIX,
+            engine.encode(ciphertext),
+            engine.encode(nonce),
+            engine.encode(tag)
+        ))
     }
 
-    pub(crate) fn cache_response<R: Runtime>(
-        app: &AppHandle<R>,
+    fn decrypt_license_key(enc: &str, storage_key: &[u8; 32]) -> Result<String> {
+        let data = enc
+            .trim()
+            .split('.')
+            .map(|v| base64::engine::general_purpose::STANDARD.decode(v))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| Error::ParseErr("Failed decoding cached license key".into()))?;
+
+        if data.len() != 3 || data[1].len() != 12 {
+            return Err(Error::ParseErr("Invalid cached license key format".into()));
+        }
+
+        // concat authentication tag with ciphertext
+        let mut ciphertext = data[0].clone();
+        ciphertext.extend_from_slice(&data[2]);
+
+        let aes = Aes256Gcm::new(Key::from_slice(storage_key));
+
+        let plaintext = aes
+            .decrypt(Nonce::from_slice(&data[1]), ciphertext.as_ref())
+            .map_err(|_| Error::ParseErr("Failed decrypting cached license key".into()))?;
+
+        String::from_utf8(plaintext)
+            .map_err(|_| Error::ParseErr("Failed decrypting cached license key".into()))
+    }
+
+    pub(crate) fn remove_cached_license_key(storage: &dyn KeygenStorage) -> Result<()> {
+        storage.remove(&StorageEntry::LicenseKey)
+    }
+
+    pub(crate) fn cache_response(
+        storage: &dyn KeygenStorage,
         license_key: &String,
+        fingerprint: &String,
+        entitlements: &[String],
         cache: KeygenResponseCache,
     ) -> Result<()> {
-        // cache path
-        let path = Self::get_response_cache_path(app, license_key)?;
-
         // cache content
         let cache_text = serde_json::to_string(&cache)
             .map_err(|_| Error::ParseErr("Failed parsing response cache to text".into()))?;
 
-        let mut f = File::create(path)?;
-        f.write_all(cache_text.as_bytes())?;
+        let entry = StorageEntry::ValidationCache(Self::get_response_cache_name(
+            license_key,
+            fingerprint,
+            entitlements,
+        ));
+
+        storage.write(&entry, &cache_text)
+    }
 
-        Ok(())
+    pub(crate) fn clear_response_cache(storage: &dyn KeygenStorage) -> Result<()> {
+        storage.clear_validation_cache()
     }
 
-    pub(crate) fn clear_response_cache<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
-        // get cache dir
-        let keygen_cache_dir = Self::get_response_cache_dir(app)?;
+    fn clear_expired_response_cache(storage: &dyn KeygenStorage, client: &KeygenClient) {
+        let names = match storage.list_validation_cache() {
+            Ok(names) => names,
+            Err(err) => {
+                dbg!(err);
+                return;
+            }
+        };
+
+        for name in names {
+            let entry = StorageEntry::ValidationCache(name);
+
+            // unreadable caches are dealt with when they're loaded
+            let Ok(Some(res_cache)) = Self::get_response_cache(storage, &entry) else {
+                continue;
+            };
 
-        if keygen_cache_dir.exists() {
-            fs::remove_dir_all(&keygen_cache_dir)?;
+            if !matches!(client.response_cache_expired(&res_cache), Ok(false)) {
+                if let Err(err) = storage.remove(&entry) {
+                    dbg!(err);
+                }
+            }
         }
+    }
 
-        Ok(())
+    fn load_response_cache(
+        storage: &dyn KeygenStorage,
+        client: &KeygenClient,
+        machine: &Machine,
+        license_key: &String,
+        entitlements: &[String],
+    ) -> Result<Option<LicenseResponse>> {
+        let name = Self::get_response_cache_name(license_key, &machine.fingerprint, entitlements);
+
+        let Some(res_cache) =
+            Self::get_response_cache(storage, &StorageEntry::ValidationCache(name.clone()))?
+        else {
+            return Ok(None);
+        };
+
+        let lic_res = Self::verify_cached_response(
+            client,
+            machine,
+            license_key,
+            &name,
+            res_cache,
+            Some(entitlements),
+        )?;
+
+        Ok(Some(lic_res))
     }
 
-    fn clear_response_cache_except<R: Runtime>(
-        app: &AppHandle<R>,
-        excluded_path: PathBuf,
-    ) -> Result<()> {
-        // get cache dir
-        let keygen_cache_dir = Self::get_response_cache_dir(app)?;
-
-        // delete all files except the excluded_path
-        if keygen_cache_dir.exists() && keygen_cache_dir.is_dir() {
-            for entry in fs::read_dir(keygen_cache_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_file() && path != excluded_path {
-                    fs::remove_file(path)?;
+    // the most recent cache that still verifies, whatever its entitlement scope
+    fn load_latest_response_cache(
+        storage: &dyn KeygenStorage,
+        client: &KeygenClient,
+        machine: &Machine,
+        license_key: &String,
+    ) -> Option<LicenseResponse> {
+        let names = match storage.list_validation_cache() {
+            Ok(names) => names,
+            Err(err) => {
+                dbg!(err);
+                return None;
+            }
+        };
+
+        let mut latest: Option<(DateTime<FixedOffset>, LicenseResponse)> = None;
+
+        for name in names {
+            let entry = StorageEntry::ValidationCache(name.clone());
+
+            let res_cache = match Self::get_response_cache(storage, &entry) {
+                Ok(Some(res_cache)) => res_cache,
+                Ok(None) => continue,
+                Err(err) => {
+                    dbg!(err);
+                    continue;
+                }
+            };
+
+            let Ok(date) = DateTime::parse_from_rfc2822(&res_cache.date) else {
+                continue;
+            };
+
+            if latest
+                .as_ref()
+                .is_some_and(|(latest_date, _)| *latest_date >= date)
+            {
+                continue;
+            }
+
+            match Self::verify_cached_response(client, machine, license_key, &name, res_cache, None)
+            {
+                Ok(lic_res) => latest = Some((date, lic_res)),
+                Err(err) => {
+                    dbg!(err);
                 }
             }
         }
 
-        Ok(())
+        latest.map(|(_, lic_res)| lic_res)
     }
 
-    fn get_response_cache<R: Runtime>(
-        app: &AppHandle<R>,
+    fn verify_cached_response(
+        client: &KeygenClient,
+        machine: &Machine,
         license_key: &String,
-    ) -> Result<Option<(KeygenResponseCache, PathBuf)>> {
-        // cache path
-        let path = Self::get_response_cache_path(app, license_key)?;
+        cache_name: &str,
+        res_cache: KeygenResponseCache,
+        entitlements: Option<&[String]>,
+    ) -> Result<LicenseResponse> {
+        // verify and parse the cache
+        let lic_res = client.verify_response_cache(res_cache)?;
 
-        // no license cache
-        if !path.exists() {
-            return Ok(None);
+        // must have been validated for this machine (and scope, when requested)
+        let entitlements = entitlements.map(|entitlements| entitlements.to_vec());
+        if !Self::scope_matches(&lic_res, &machine.fingerprint, entitlements.as_ref()) {
+            return Err(Error::BadCache("Validation scope didn't match".into()));
         }
 
-        // cache content
-        let cache_text = fs::read_to_string(&path)?;
-        let cache_json: serde_json::Value = serde_json::from_str(&cache_text)
-            .map_err(|_| Error::ParseErr("Failed parsing response cache to json".into()))?;
-        let cache: KeygenResponseCache = serde_json::from_value(cache_json)
-            .map_err(|_| Error::ParseErr("Failed deserializing response cache".into()))?;
+        // must be the cached license key
+        let lic_key = lic_res
+            .data
+            .as_ref()
+            .map(|data| data.attributes.key.clone())
+            .ok_or_else(|| Error::BadCache("Missing data on cached response".into()))?;
+
+        if &lic_key != license_key {
+            return Err(Error::BadCache("Cached license key didn't match".into()));
+        }
+
+        // the signed scope must hash to the cache name
+        let scoped_entitlements = lic_res
+            .meta
+            .scope
+            .as_ref()
+            .and_then(|scope| scope.entitlements.clone())
+            .unwrap_or_default();
 
-        Ok(Some((cache, path)))
+        if Self::get_response_cache_name(&lic_key, &machine.fingerprint, &scoped_entitlements)
+            != cache_name
+        {
+            return Err(Error::BadCache("Validation cache name didn't match".into()));
+        }
+
+        Ok(lic_res)
     }
 
-    fn get_response_cache_path<R: Runtime>(
-        app: &AppHandle<R>,
-        license_key: &String,
-    ) -> Result<PathBuf> {
-        // get cache dir
-        let keygen_cache_dir = Self::get_response_cache_dir(app)?;
+    fn get_response_cache(
+        storage: &dyn KeygenStorage,
+        entry: &StorageEntry,
+    ) -> Result<Option<KeygenResponseCache>> {
+        // no license cache
+        let Some(cache_text) = storage.read(entry)? else {
+            return Ok(None);
+        };
 
-        if !keygen_cache_dir.exists() {
-            fs::create_dir_all(&keygen_cache_dir)?;
+        // corrupt cache: move it out of the way
+        match serde_json::from_str::<KeygenResponseCache>(&cache_text) {
+            Ok(cache) => Ok(Some(cache)),
+            Err(err) => {
+                dbg!(err);
+                storage.quarantine(entry)?;
+                Ok(None)
+            }
         }
+    }
+
+    // one cache per (license key, fingerprint, entitlement set).
+    // expiry is based on the signed response date, not on the name.
+    fn get_response_cache_name(
+        license_key: &String,
+        fingerprint: &String,
+        entitlements: &[String],
+    ) -> String {
+        let mut entitlements = entitlements.to_vec();
+        entitlements.sort();
+        entitlements.dedup();
 
-        // get path
         let mut hasher = Sha256::new();
-        hasher.update(format!("{}:{}", license_key, Local::now().date_naive(),));
-        let path = format!("{:x}", hasher.finalize());
+        hasher.update(format!(
+            "{}:{}:{}",
+            license_key,
+            fingerprint,
+            entitlements.join(","),
+        ));
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LicenseResponse, LicensedState, LICENSE_KEY_ENC_PREFIX};
+    use crate::{
+        client::{
+            sig::KeygenSig,
+            tests::{client, signing_key},
+            KeygenClient, KeygenResponseCache,
+        },
+        machine::Machine,
+        storage::{KeygenStorage, MemoryStorage, StorageEntry},
+    };
+    use base64::Engine;
+    use chrono::Utc;
+    use ed25519_dalek::Signer;
+    use reqwest::Method;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
 
-        // dir path
-        let dir_path = keygen_cache_dir.join(path);
+    static KEY: &str = "ABCD-EFGH";
 
-        Ok(dir_path)
+    fn machine(fingerprint: &str) -> Machine {
+        Machine::new(
+            fingerprint.into(),
+            None,
+            "app".into(),
+            "1.0.0".into(),
+            "secret".into(),
+            Default::default(),
+            None,
+        )
     }
 
-    fn get_response_cache_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
-        // get app data dir
-        let Ok(data_dir) = app.path().app_data_dir() else {
-            return Err(Error::PathErr("Can't resolve app data dir".into()));
+    fn validation(key: &str, scope: Value) -> Value {
+        json!({
+            "meta": {
+                "ts": Utc::now().to_rfc3339(),
+                "valid": true,
+                "detail": "is valid",
+                "code": "VALID",
+                "scope": scope,
+            },
+            "data": {
+                "id": "license",
+                "attributes": {
+                    "name": null,
+                    "key": key,
+                    "expiry": null,
+                    "metadata": {},
+                },
+                "relationships": {
+                    "policy": { "data": { "id": "policy" } },
+                },
+            },
+        })
+    }
+
+    fn license_response(scope: Value) -> LicenseResponse {
+        serde_json::from_value(validation(KEY, scope)).unwrap()
+    }
+
+    fn signed_cache(client: &KeygenClient, body: &Value) -> KeygenResponseCache {
+        let url = client.validate_key_url().unwrap();
+
+        let mut res_cache = KeygenResponseCache {
+            sig: String::new(),
+            target: KeygenSig::request_target(Method::POST.as_str(), &url),
+            host: url.host_str().unwrap().into(),
+            date: Utc::now().to_rfc2822(),
+            body: body.to_string(),
         };
 
-        // get cache dir
-        let keygen_cache_dir = data_dir.join("keygen/validation_cache");
+        let data = KeygenSig::from_response_cache(res_cache.clone()).data();
+        res_cache.sig = base64::engine::general_purpose::STANDARD
+            .encode(signing_key().sign(data.as_bytes()).to_bytes());
+
+        res_cache
+    }
+
+    fn entitlements(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn scope_matches_fingerprint() {
+        let fingerprint = "fingerprint".to_string();
+
+        let single = license_response(json!({ "fingerprint": "fingerprint" }));
+        assert!(LicensedState::scope_matches(&single, &fingerprint, None));
+
+        let several = license_response(json!({ "fingerprints": ["legacy", "fingerprint"] }));
+        assert!(LicensedState::scope_matches(&several, &fingerprint, None));
+
+        let other = license_response(json!({ "fingerprint": "other" }));
+        assert!(!LicensedState::scope_matches(&other, &fingerprint, None));
+
+        let unscoped = license_response(Value::Null);
+        assert!(!LicensedState::scope_matches(&unscoped, &fingerprint, None));
+    }
+
+    #[test]
+    fn scope_matches_entitlements_as_sets() {
+        let fingerprint = "fingerprint".to_string();
+        let lic_res = license_response(json!({
+            "fingerprint": "fingerprint",
+            "entitlements": ["B", "A"],
+        }));
+
+        let matches = |codes: &[&str]| {
+            LicensedState::scope_matches(&lic_res, &fingerprint, Some(&entitlements(codes)))
+        };
+
+        assert!(matches(&["A", "B"]));
+        assert!(matches(&["B", "A", "A"]));
+        assert!(!matches(&["A"]));
+        assert!(!matches(&["A", "B", "C"]));
+        assert!(!matches(&[]));
+    }
+
+    #[test]
+    fn response_cache_name_ignores_entitlement_order() {
+        let key = KEY.to_string();
+        let fingerprint = "fingerprint".to_string();
+        let name = |key: &String, codes: &[&str]| {
+            LicensedState::get_response_cache_name(key, &fingerprint, &entitlements(codes))
+        };
+
+        assert_eq!(name(&key, &["A", "B"]), name(&key, &["B", "A", "B"]));
+        assert_ne!(name(&key, &["A", "B"]), name(&key, &["A"]));
+        assert_ne!(name(&key, &["A"]), name(&"OTHER-KEY".to_string(), &["A"]));
+    }
+
+    #[test]
+    fn verify_cached_response_binds_name() {
+        let client = client(0, Arc::new(MemoryStorage::default()));
+        let machine = machine("fingerprint");
+        let key = KEY.to_string();
+
+        let body = validation(
+            KEY,
+            json!({ "fingerprint": "fingerprint", "entitlements": ["A"] }),
+        );
+        let res_cache = signed_cache(&client, &body);
+
+        let name = |codes: &[&str]| {
+            LicensedState::get_response_cache_name(&key, &machine.fingerprint, &entitlements(codes))
+        };
+        let verify = |machine: &Machine, key: &String, cache_name: &str| {
+            LicensedState::verify_cached_response(
+                &client,
+                machine,
+                key,
+                cache_name,
+                res_cache.clone(),
+                None,
+            )
+        };
+
+        assert!(verify(&machine, &key, &name(&["A"])).is_ok());
+        // a response for another scope copied under this name
+        assert!(verify(&machine, &key, &name(&[])).is_err());
+        assert!(verify(&machine, &"OTHER-KEY".to_string(), &name(&["A"])).is_err());
+        assert!(verify(&self::machine("other"), &key, &name(&["A"])).is_err());
+
+        // tampered body
+        let mut tampered = res_cache.clone();
+        tampered.body = validation(
+            KEY,
+            json!({ "fingerprint": "fingerprint", "entitlements": [] }),
+        )
+        .to_string();
+        assert!(LicensedState::verify_cached_response(
+            &client,
+            &machine,
+            &key,
+            &name(&[]),
+            tampered,
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn license_key_encrypted_at_rest() {
+        let storage = MemoryStorage::default();
+        let machine = machine("fingerprint");
+
+        LicensedState::cache_license_key(KEY, &storage, &machine).unwrap();
+
+        let content = storage.read(&StorageEntry::LicenseKey).unwrap().unwrap();
+        assert!(content.starts_with(LICENSE_KEY_ENC_PREFIX));
+        assert!(!content.contains(KEY));
+
+        let enc = content.strip_prefix(LICENSE_KEY_ENC_PREFIX).unwrap();
+        assert_eq!(
+            LicensedState::decrypt_license_key(enc, &machine.storage_key()).unwrap(),
+            KEY
+        );
+
+        assert_eq!(
+            LicensedState::get_cached_license_key(&storage, &machine).unwrap(),
+            Some(KEY.to_string())
+        );
+
+        // cached on another machine
+        assert_eq!(
+            LicensedState::get_cached_license_key(&storage, &self::machine("other")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn plaintext_license_key_is_migrated() {
+        let storage = MemoryStorage::default();
+        let machine = machine("fingerprint");
+
+        storage.write(&StorageEntry::LicenseKey, KEY).unwrap();
+
+        assert_eq!(
+            LicensedState::get_cached_license_key(&storage, &machine).unwrap(),
+            Some(KEY.to_string())
+        );
+
+        let content = storage.read(&StorageEntry::LicenseKey).unwrap().unwrap();
+        assert!(content.starts_with(LICENSE_KEY_ENC_PREFIX));
+        assert!(!content.contains(KEY));
 
-        Ok(keygen_cache_dir)
+        assert_eq!(
+            LicensedState::get_cached_license_key(&storage, &machine).unwrap(),
+            Some(KEY.to_string())
+        );
     }
 }
@@ -0,0 +1,137 @@
+use super::LicensedState;
+use crate::{
+    client::KeygenClient,
+    err::{Error, ErrorSummary},
+    events,
+    machine::Machine,
+    storage::Storage,
+    Result,
+};
+use rand::Rng;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::Mutex;
+
+// first retry while Keygen can't be reached, doubled on each attempt
+static OFFLINE_RETRY: Duration = Duration::from_secs(60);
+
+// failed revalidations back off up to this many intervals
+static MAX_BACKOFF_INTERVALS: u32 = 4;
+
+// shorter than validateKey()'s: a dropped connection shouldn't hold the machine and client
+static REVALIDATE_TIMEOUT: Duration = Duration::from_secs(20);
+
+// revalidate the current license in the background,
+// so a license suspended or revoked on Keygen doesn't stay valid until restart
+pub(crate) fn spawn<R: Runtime>(app: AppHandle<R>, interval: Duration) {
+    tauri::async_runtime::spawn(async move {
+        let mut offline_attempts = 0;
+        let mut failures = 0;
+        let mut last_error: Option<String> = None;
+        let mut delay = interval;
+
+        loop {
+            tokio::time::sleep(jitter(delay)).await;
+
+            delay = match revalidate(&app).await {
+                Ok(()) => {
+                    offline_attempts = 0;
+                    failures = 0;
+                    last_error = None;
+                    interval
+                }
+                // network unreachable: retry sooner, backing off up to the interval
+                Err(Error::RequestError(err)) => {
+                    dbg!(err);
+
+                    if offline_attempts == 0 {
+                        offline(&app).await;
+                    }
+
+                    offline_attempts += 1;
+                    backoff(OFFLINE_RETRY, offline_attempts).min(interval)
+                }
+                Err(err) => {
+                    dbg!(&err);
+                    offline_attempts = 0;
+                    failures += 1;
+
+                    let err: ErrorSummary = err.into();
+
+                    // only when the failure changed
+                    if last_error.as_ref() != Some(&err.code) {
+                        last_error = Some(err.code.clone());
+                        let licensed_state = app.state::<Mutex<LicensedState>>();
+                        events::error(&app, &*licensed_state.lock().await, &err);
+                    }
+
+                    backoff(interval, failures).min(interval * MAX_BACKOFF_INTERVALS)
+                }
+            };
+        }
+    });
+}
+
+async fn revalidate<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
+    let storage = app.state::<Storage>();
+    let machine = app.state::<Mutex<Machine>>();
+    let client = app.state::<Mutex<KeygenClient>>();
+    let licensed_state = app.state::<Mutex<LicensedState>>();
+
+    let mut machine = machine.lock().await;
+    let client = client.lock().await;
+
+    // revalidate a copy: getLicense() and entitlement checks aren't blocked by the request
+    let mut revalidated = licensed_state.lock().await.clone();
+    let previous = revalidated.get_license();
+
+    // nothing to revalidate, e.g. after resetLicense()
+    if previous.is_none() {
+        return Ok(());
+    }
+
+    let Some(key) = LicensedState::get_cached_license_key(storage.as_ref(), &machine)? else {
+        return Ok(());
+    };
+
+    let entitlements = revalidated.entitlements.clone();
+    let cache_valid_response = revalidated.cache_valid_response;
+
+    let result = revalidated
+        .validate_and_update(
+            app,
+            storage.as_ref(),
+            &mut machine,
+            &client,
+            key,
+            entitlements,
+            cache_valid_response,
+            REVALIDATE_TIMEOUT,
+        )
+        .await;
+
+    let mut licensed_state = licensed_state.lock().await;
+
+    // only when nothing else changed the state meanwhile
+    if licensed_state.get_license() == previous {
+        *licensed_state = revalidated;
+    }
+
+    result.map(|_| ())
+}
+
+async fn offline<R: Runtime>(app: &AppHandle<R>) {
+    let licensed_state = app.state::<Mutex<LicensedState>>();
+    let licensed_state = licensed_state.lock().await;
+
+    events::offline(app, &licensed_state);
+}
+
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+}
+
+// +/- 10%, so apps started together don't hit Keygen together
+fn jitter(delay: Duration) -> Duration {
+    delay.mul_f64(rand::thread_rng().gen_range(0.9..1.1))
+}
@@ -8,12 +8,19 @@ pub struct LicenseResponse {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct LicenseMeta {
-    #[allow(dead_code)]
     pub ts: String,
     pub valid: bool,
     pub detail: String,
     pub code: String,
-    pub scope: Option<serde_json::Value>,
+    pub nonce: Option<u64>,
+    pub scope: Option<LicenseScope>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LicenseScope {
+    pub fingerprint: Option<String>,
+    pub fingerprints: Option<Vec<String>>,
+    pub entitlements: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
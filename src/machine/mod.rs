@@ -1,27 +1,24 @@
+pub mod renewal;
 pub mod types;
 
 use crate::{
-    client::KeygenClient,
-    err::{parse_err_json, Error},
-    licensed::LicensedState,
+    client::{sig::SignatureAlgorithm, KeygenClient},
+    err::Error,
+    licensed::{license::License, LicensedState},
+    storage::{KeygenStorage, StorageEntry},
     Result,
 };
 use aes_gcm::aead::{Aead, NewAead};
 use aes_gcm::{Aes256Gcm, Key, Nonce, Tag};
 use base64::Engine;
-use chrono::{DateTime, Utc};
+use chrono::DateTime;
 use reqwest::{Method, StatusCode};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest as ShaDigest, Sha256};
-use std::{
-    fs::{self, File},
-    io::Write,
-    path::PathBuf,
-    time::Duration,
-};
+use std::{sync::Arc, time::Duration};
 
-use tauri::{webview_version, AppHandle, Manager, Runtime};
-use types::{MachineFileRes, MachineLicense};
+use tauri::{webview_version, PackageInfo};
+use types::{CheckoutSettings, MachineFileRes, MachineLicense};
 
 #[cfg(target_os = "linux")]
 static ENGINE_NAME: &str = "WebKit";
@@ -32,12 +29,30 @@ static ENGINE_NAME: &str = "WebKit";
 #[cfg(target_os = "windows")]
 static ENGINE_NAME: &str = "WebView2";
 
+// validation codes for a license that isn't activated with the current fingerprint
+static FINGERPRINT_MISMATCH_CODES: [&str; 3] =
+    ["FINGERPRINT_SCOPE_MISMATCH", "NO_MACHINE", "NO_MACHINES"];
+
+// extra machine metadata, e.g. app version and install channel
+pub(crate) type MachineMetadata =
+    Arc<dyn Fn(&PackageInfo) -> serde_json::Map<String, serde_json::Value> + Send + Sync>;
+
 #[derive(Debug)]
 pub struct Machine {
     pub fingerprint: String,
+    pub legacy_fingerprint: Option<String>, // raw fingerprint, until migrated to the hashed one
+    // last activated fingerprint, when the machine id changed.
+    // only picks the machine to deactivate, never used to decrypt.
+    pub previous_fingerprint: Option<String>,
     pub name: String,
     pub platform: String,
+    pub hostname: String,
+    pub ip: String,
+    pub cores: usize,
+    pub app_version: String,
+    pub metadata: serde_json::Map<String, serde_json::Value>,
     pub user_agent: String,
+    storage_secret: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -48,9 +63,29 @@ struct MachineFile {
 }
 
 impl Machine {
-    pub(crate) fn new(app_name: String, app_version: String) -> Self {
-        let fingerprint = machine_uid::get().unwrap_or("".into());
-        let name = whoami::devicename();
+    pub(crate) fn new(
+        fingerprint: String,
+        legacy_fingerprint: Option<String>,
+        app_name: String,
+        app_version: String,
+        storage_secret: String,
+        metadata: serde_json::Map<String, serde_json::Value>,
+        user: Option<String>,
+    ) -> Self {
+        // e.g. "MacBook Pro (alice)" in user locked mode
+        let name = match user {
+            Some(user) => format!("{} ({})", whoami::devicename(), user),
+            None => whoami::devicename(),
+        };
+        let hostname = whoami::fallible::hostname().unwrap_or_default();
+        let ip = local_ip_address::local_ip()
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+
+        // for core limited policies
+        let cores = std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1);
 
         // platform
         let os_name = format!("{}", whoami::platform());
@@ -69,16 +104,52 @@ impl Machine {
 
         Self {
             fingerprint,
+            legacy_fingerprint,
+            previous_fingerprint: None,
             name,
             platform,
+            hostname,
+            ip,
+            cores,
+            app_version,
+            metadata,
             user_agent,
+            storage_secret,
         }
     }
 
+    // key for encrypting data at rest:
+    // bound to this machine and to the app
+    pub(crate) fn storage_key(&self) -> [u8; 32] {
+        self.derive_storage_key(&self.fingerprint)
+    }
+
+    // storage keys from the raw fingerprint of this machine, before hashing.
+    // not from the previous fingerprint: it's read from storage, so a copied
+    // data dir would decrypt on another machine.
+    pub(crate) fn fallback_storage_keys(&self) -> Vec<[u8; 32]> {
+        self.fallback_fingerprints()
+            .into_iter()
+            .map(|fingerprint| self.derive_storage_key(fingerprint))
+            .collect()
+    }
+
+    fn fallback_fingerprints(&self) -> Vec<&String> {
+        self.legacy_fingerprint.iter().collect()
+    }
+
+    fn derive_storage_key(&self, fingerprint: &str) -> [u8; 32] {
+        let mut sha = Sha256::new();
+        sha.update([fingerprint.as_bytes(), b":", self.storage_secret.as_bytes()].concat());
+
+        sha.finalize().into()
+    }
+
     pub(crate) async fn activate(
-        &self,
+        &mut self,
         licensed_state: &mut LicensedState,
         client: &KeygenClient,
+        storage: &dyn KeygenStorage,
     ) -> Result<()> {
         // get license
         let license = licensed_state
@@ -97,16 +168,200 @@ impl Machine {
             });
         }
 
+        self.activate_fingerprint(&self.fingerprint, &license, client)
+            .await?;
+
+        self.record_activated_fingerprint(storage)?;
+
+        Ok(())
+    }
+
+    // move an activation from the raw fingerprint to the hashed one
+    pub(crate) async fn migrate_fingerprint(
+        &mut self,
+        license: &License,
+        client: &KeygenClient,
+        storage: &dyn KeygenStorage,
+    ) -> Result<()> {
+        let Some(legacy_fingerprint) = self.legacy_fingerprint.clone() else {
+            return Ok(());
+        };
+
+        self.move_activation(&legacy_fingerprint, license, client)
+            .await?;
+
+        self.record_activated_fingerprint(storage)
+    }
+
+    // the machine id changed since the last activation:
+    // move the stale machine's activation to this fingerprint.
+    // returns whether an activation was moved.
+    pub(crate) async fn migrate_changed_fingerprint(
+        &mut self,
+        license: &License,
+        client: &KeygenClient,
+        storage: &dyn KeygenStorage,
+    ) -> Result<bool> {
+        let Some(previous_fingerprint) = self.previous_fingerprint.clone() else {
+            return Ok(false);
+        };
+
+        // only when this fingerprint isn't activated
+        if !FINGERPRINT_MISMATCH_CODES.contains(&license.code.as_str()) {
+            return Ok(false);
+        }
+
+        // make sure fingerprint is not an empty string
+        if self.fingerprint.is_empty() {
+            return Err(Error::LicenseErr {
+                code: "NO_FINGERPRINT".into(),
+                detail: "Can't migrate this machine. Failed parsing machine fingerprint".into(),
+            });
+        }
+
+        let moved = self
+            .move_activation(&previous_fingerprint, license, client)
+            .await?;
+
+        self.record_activated_fingerprint(storage)?;
+
+        Ok(moved)
+    }
+
+    // deactivate `from`, then activate this fingerprint in its place.
+    // returns false when `from` wasn't activated.
+    async fn move_activation(
+        &self,
+        from: &str,
+        license: &License,
+        client: &KeygenClient,
+    ) -> Result<bool> {
+        // free the stale fingerprint's seat first
+        match Self::deactivate_fingerprint(from, license, client).await {
+            Ok(()) => {}
+            // never activated under that fingerprint: nothing to move
+            Err(Error::ApiErr { code, .. }) if code == "NOT_FOUND" => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        match self
+            .activate_fingerprint(&self.fingerprint, license, client)
+            .await
+        {
+            Ok(()) => Ok(true),
+            // already activated under this fingerprint
+            Err(Error::ApiErr { code, .. }) if code == "FINGERPRINT_TAKEN" => Ok(true),
+            Err(err) => {
+                // don't leave the license without its seat
+                if let Err(err) = self.activate_fingerprint(from, license, client).await {
+                    dbg!(err);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    // remember which fingerprint this machine is activated with.
+    // activations send the attributes of this app version.
+    fn record_activated_fingerprint(&mut self, storage: &dyn KeygenStorage) -> Result<()> {
+        storage.write(&StorageEntry::ActivatedFingerprint, &self.fingerprint)?;
+        self.legacy_fingerprint = None;
+        self.previous_fingerprint = None;
+
+        self.record_reported_version(storage)
+    }
+
+    // a valid validation means this machine is activated with its fingerprint.
+    // e.g. installs from before the fingerprint was recorded
+    pub(crate) fn record_validated_fingerprint(&self, storage: &dyn KeygenStorage) -> Result<()> {
+        if storage.read(&StorageEntry::ActivatedFingerprint)?.is_some() {
+            return Ok(());
+        }
+
+        storage.write(&StorageEntry::ActivatedFingerprint, &self.fingerprint)
+    }
+
+    fn record_reported_version(&self, storage: &dyn KeygenStorage) -> Result<()> {
+        storage.write(&StorageEntry::ReportedVersion, &self.app_version)
+    }
+
+    // send the machine attributes again when the app version changed
+    // since they were last sent, e.g. on the first run after an app update
+    pub(crate) async fn update_if_version_changed(
+        &self,
+        licensed_state: &LicensedState,
+        client: &KeygenClient,
+        storage: &dyn KeygenStorage,
+    ) -> Result<()> {
+        let reported_version = storage.read(&StorageEntry::ReportedVersion)?;
+
+        if reported_version.as_deref().map(str::trim) == Some(self.app_version.as_str()) {
+            return Ok(());
+        }
+
+        self.update(licensed_state, client, storage).await
+    }
+
+    // detect a changed machine id, from the last activated fingerprint
+    pub(crate) fn load_previous_fingerprint(&mut self, storage: &dyn KeygenStorage) -> Result<()> {
+        let previous = storage.read(&StorageEntry::ActivatedFingerprint)?;
+
+        self.previous_fingerprint =
+            previous
+                .map(|previous| previous.trim().to_string())
+                .filter(|previous| {
+                    !previous.is_empty()
+                    && previous != &self.fingerprint
+                    // hashing migration is handled separately
+                    && Some(previous) != self.legacy_fingerprint.as_ref()
+                });
+
+        Ok(())
+    }
+
+    // the machine file is encrypted for the fingerprint it was checked out with:
+    // check out a new one for this fingerprint
+    pub(crate) async fn rekey_machine_file(
+        &self,
+        licensed_state: &LicensedState,
+        client: &KeygenClient,
+        storage: &dyn KeygenStorage,
+    ) -> Result<()> {
+        if storage.read(&StorageEntry::MachineFile)?.is_none() {
+            return Ok(());
+        }
+
+        match Self::get_checkout_settings(storage)? {
+            Some(settings) => {
+                self.checkout(
+                    licensed_state,
+                    client,
+                    storage,
+                    settings.ttl_seconds,
+                    settings.ttl_forever,
+                )
+                .await
+            }
+            None => Self::remove_machine_file(storage),
+        }
+    }
+
+    async fn activate_fingerprint(
+        &self,
+        fingerprint: &str,
+        license: &License,
+        client: &KeygenClient,
+    ) -> Result<()> {
         // prepare request
         let url = client.build_url("machines".into(), None)?;
+        let mut attributes = self.attributes();
+        attributes["fingerprint"] = serde_json::json!(fingerprint);
+
         let body = serde_json::json!({
             "data": {
                 "type": "machines",
-                "attributes": {
-                    "fingerprint": self.fingerprint,
-                    "name": self.name,
-                    "platform": self.platform
-                },
+                "attributes": attributes,
                 "relationships": {
                     "license": {
                         "data": {
@@ -142,15 +397,147 @@ impl Machine {
                     Err(err) => Err(err),
                 }
             }
-            status_code => Err(parse_err_json(status_code, res_json)),
+            status_code => Err(client.verify_err_response(
+                Method::POST.to_string(),
+                url,
+                status_code,
+                res_headers,
+                res_text,
+                res_json,
+            )),
+        }
+    }
+
+    fn attributes(&self) -> serde_json::Value {
+        let mut attributes = serde_json::json!({
+            "name": self.name,
+            "platform": self.platform,
+            "cores": self.cores,
+        });
+
+        if !self.hostname.is_empty() {
+            attributes["hostname"] = serde_json::json!(self.hostname);
+        }
+
+        if !self.ip.is_empty() {
+            attributes["ip"] = serde_json::json!(self.ip);
+        }
+
+        if !self.metadata.is_empty() {
+            attributes["metadata"] = serde_json::json!(self.metadata);
+        }
+
+        attributes
+    }
+
+    // e.g. after an app update, or a hardware change
+    pub(crate) async fn update(
+        &self,
+        licensed_state: &LicensedState,
+        client: &KeygenClient,
+        storage: &dyn KeygenStorage,
+    ) -> Result<()> {
+        // get license
+        let license = licensed_state
+            .get_license()
+            .ok_or_else(|| Error::LicenseErr {
+                code: "NO_LICENSE".into(),
+                detail: "Can't update machine. Current app state has no license. Call validateKey() first."
+                    .into(),
+            })?;
+
+        // make sure fingerprint is not an empty string
+        if self.fingerprint.is_empty() {
+            return Err(Error::LicenseErr {
+                code: "NO_FINGERPRINT".into(),
+                detail: "Can't update this machine. Failed parsing machine fingerprint".into(),
+            });
+        }
+
+        // prepare request
+        let url = client.build_url(format!("machines/{}", self.fingerprint), None)?;
+        let body = serde_json::json!({
+            "data": {
+                "type": "machines",
+                "attributes": self.attributes()
+            }
+        });
+
+        // request machine update
+        let response = client
+            .patch(url.to_string())
+            .timeout(Duration::from_secs(90))
+            .header("Content-Type", "application/vnd.api+json")
+            .header("Accept", "application/vnd.api+json")
+            .header("Authorization", format!("License {}", license.key))
+            .json(&body)
+            .send()
+            .await?;
+
+        // extract response
+        let res_status = response.status();
+        let res_headers = response.headers().clone();
+        let (res_text, res_json) = client.res_text_json(response).await?;
+
+        match res_status {
+            StatusCode::OK => {
+                // verify signature
+                client.verify_response(Method::PATCH.to_string(), url, res_headers, res_text)?;
+
+                self.record_reported_version(storage)
+            }
+            status_code => Err(client.verify_err_response(
+                Method::PATCH.to_string(),
+                url,
+                status_code,
+                res_headers,
+                res_text,
+                res_json,
+            )),
+        }
+    }
+
+    async fn deactivate_fingerprint(
+        fingerprint: &str,
+        license: &License,
+        client: &KeygenClient,
+    ) -> Result<()> {
+        let url = client.build_url(format!("machines/{}", fingerprint), None)?;
+
+        // request machine deactivation
+        let response = client
+            .delete(url.to_string())
+            .timeout(Duration::from_secs(90))
+            .header("Accept", "application/vnd.api+json")
+            .header("Authorization", format!("License {}", license.key))
+            .send()
+            .await?;
+
+        // no content to verify
+        let res_status = response.status();
+        if res_status == StatusCode::NO_CONTENT {
+            return Ok(());
         }
+
+        // extract response
+        let res_headers = response.headers().clone();
+        let (res_text, res_json) = client.res_text_json(response).await?;
+
+        Err(client.verify_err_response(
+            Method::DELETE.to_string(),
+            url,
+            res_status,
+            res_headers,
+            res_text,
+            res_json,
+        ))
     }
 
-    pub(crate) async fn checkout<R: Runtime>(
+    pub(crate) async fn checkout(
         &self,
         licensed_state: &LicensedState,
         client: &KeygenClient,
-        app: &AppHandle<R>,
+        storage: &dyn KeygenStorage,
         ttl_seconds: u32,
         ttl_forever: bool,
     ) -> Result<()> {
@@ -180,7 +567,7 @@ impl Machine {
         }
 
         // license shouldn't be expired yet
-        if license.has_expired() && !license.should_maintain_access() {
+        if license.has_expired(client.now()) && !license.should_maintain_access() {
             return Err(Error::LicenseErr {
                 code: "EXPIRED".into(),
                 detail: "Can't checkout machine file. License has expired".into(),
@@ -194,12 +581,15 @@ impl Machine {
         ];
 
         // ttl should be min 1 hour max 1 year
+        let requested_ttl_seconds = ttl_seconds;
         let ttl_seconds = ttl_seconds.clamp(3600, 31_556_952);
 
         // make sure ttl doesn't exceed license expiry
         let expiry_date = DateTime::parse_from_rfc3339(&license.expiry.clone().unwrap())
             .map_err(|_| Error::ParseErr("Failed parsing license expiry date".into()))?;
-        let seconds_to_expiry = expiry_date.signed_duration_since(Utc::now()).num_seconds();
+        let seconds_to_expiry = expiry_date
+            .signed_duration_since(client.now())
+            .num_seconds();
         let ttl_seconds: i64 = std::cmp::min(seconds_to_expiry, ttl_seconds.into());
         let ttl = ttl_seconds.to_string();
 
@@ -243,47 +633,79 @@ impl Machine {
                         // get certificate
                         let cert = machine_file_res.data.attributes.certificate;
 
-                        // save to '[APP_DATA]/keygen/machine.lic'
-                        self.save_machine_file(cert, app)?;
+                        // save to '[APP_DATA]/keygen/machine.lic' by default
+                        self.save_machine_file(cert, storage)?;
+
+                        // remember the requested ttl for renewals
+                        Self::save_checkout_settings(
+                            CheckoutSettings {
+                                ttl_seconds: requested_ttl_seconds,
+                                ttl_forever,
+                            },
+                            storage,
+                        )?;
 
                         Ok(())
                     }
                     Err(err) => Err(err),
                 }
             }
-            code => Err(parse_err_json(code, res_json)),
+            status_code => Err(client.verify_err_response(
+                Method::POST.to_string(),
+                url,
+                status_code,
+                res_headers,
+                res_text,
+                res_json,
+            )),
         }
     }
 
-    pub(crate) fn load_machine_file<R: Runtime>(
+    pub(crate) fn load_machine_file(
         &self,
         license_key: &String,
         client: &KeygenClient,
-        app: &AppHandle<R>,
+        storage: &dyn KeygenStorage,
     ) -> Result<Option<MachineLicense>> {
-        // machine file path
-        let path = Self::get_machine_file_path(app)?;
-
         // no machine file
-        if !path.exists() {
+        let Some(cert) = storage.read(&StorageEntry::MachineFile)? else {
             return Ok(None);
-        }
+        };
 
-        // load cert
-        let cert = fs::read_to_string(path)?;
+        // corrupt machine file, e.g. a truncated write
+        let machine_file = match Self::parse_machine_file(cert) {
+            Ok(machine_file) => machine_file,
+            Err(err) => {
+                dbg!(err);
+                storage.quarantine(&StorageEntry::MachineFile)?;
+                return Ok(None);
+            }
+        };
 
         // decrypt cert
-        let machine_license = self.decrypt_machine_file(cert, license_key, client)?;
+        let err = match self.decrypt_machine_file(
+            &machine_file,
+            license_key,
+            &self.fingerprint,
+            client,
+        ) {
+            Ok(machine_license) => return Ok(Some(machine_license)),
+            Err(err) => err,
+        };
+
+        // checked out under the raw fingerprint, before hashing
+        for fingerprint in self.fallback_fingerprints() {
+            if let Ok(machine_license) =
+                self.decrypt_machine_file(&machine_file, license_key, fingerprint, client)
+            {
+                return Ok(Some(machine_license));
+            }
+        }
 
-        Ok(Some(machine_license))
+        Err(err)
     }
 
-    fn decrypt_machine_file(
-        &self,
-        cert: String,
-        license_key: &String,
-        client: &KeygenClient,
-    ) -> Result<MachineLicense> {
+    fn parse_machine_file(cert: String) -> Result<MachineFile> {
         // Extract the encoded payload from the machine file.
         let enc = cert
             .replace("-----BEGIN MACHINE FILE-----", "")
@@ -302,34 +724,47 @@ impl Machine {
         let lic: MachineFile = serde_json::from_str(payload.as_str())
             .map_err(|_| Error::ParseErr("failed deserializing machine file".into()))?;
 
+        Ok(lic)
+    }
+
+    fn decrypt_machine_file(
+        &self,
+        lic: &MachineFile,
+        license_key: &String,
+        fingerprint: &str,
+        client: &KeygenClient,
+    ) -> Result<MachineLicense> {
         // Assert algorithm is supported.
-        if !lic.alg.eq("aes-256-gcm+ed25519") {
-            return Err(Error::ParseErr("algorithm is not supported".into()));
-        }
+        let sig_algorithm = lic
+            .alg
+            .strip_prefix("aes-256-gcm+")
+            .and_then(|alg| alg.parse::<SignatureAlgorithm>().ok())
+            .ok_or_else(|| Error::ParseErr("algorithm is not supported".into()))?;
 
         // Verify the machine file's signature.
         let msg = format!("machine/{}", lic.enc);
-        client.verify_signature(msg, lic.sig.to_string())?;
+        client.verify_signature(sig_algorithm, msg, lic.sig.to_string())?;
 
         // hash the license key and machine id to obtain decryption key
         let mut sha = Sha256::new();
-        let secret = [license_key.as_bytes(), self.fingerprint.as_bytes()].concat();
+        let secret = [license_key.as_bytes(), fingerprint.as_bytes()].concat();
 
         sha.update(secret);
 
         let digest = sha.finalize();
 
         // Parse the encrypted data.
-        let data: Vec<_> = lic
+        let data = lic
             .enc
             .trim()
             .split('.')
-            .map(|v| {
-                base64::engine::general_purpose::STANDARD
-                    .decode(v)
-                    .expect("failed to parse encrypted data")
-            })
-            .collect();
+            .map(|v| base64::engine::general_purpose::STANDARD.decode(v))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| Error::ParseErr("Failed parsing encrypted data".into()))?;
+
+        if data.len() != 3 {
+            return Err(Error::ParseErr("Invalid encrypted data format".into()));
+        }
 
         // Set up data and AES-GCM
         let mut ciphertext = Vec::from(data[0].as_slice());
@@ -359,39 +794,39 @@ impl Machine {
         Ok(machine_license)
     }
 
-    fn save_machine_file<R: Runtime>(&self, cert: String, app: &AppHandle<R>) -> Result<()> {
-        let path = Self::get_machine_file_path(app)?;
-
-        let mut f = File::create(path)?;
-        f.write_all(cert.as_bytes())?;
+    fn save_machine_file(&self, cert: String, storage: &dyn KeygenStorage) -> Result<()> {
+        storage.write(&StorageEntry::MachineFile, &cert)
+    }
 
-        Ok(())
+    pub(crate) fn remove_machine_file(storage: &dyn KeygenStorage) -> Result<()> {
+        storage.remove(&StorageEntry::MachineFile)?;
+        storage.remove(&StorageEntry::CheckoutSettings)
     }
 
-    pub(crate) fn remove_machine_file<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
-        let path = Self::get_machine_file_path(app)?;
-        if path.exists() {
-            fs::remove_file(path)?;
-        }
-        Ok(())
+    fn save_checkout_settings(
+        settings: CheckoutSettings,
+        storage: &dyn KeygenStorage,
+    ) -> Result<()> {
+        let content = serde_json::to_string(&settings)
+            .map_err(|_| Error::ParseErr("Failed serializing checkout settings".into()))?;
+
+        storage.write(&StorageEntry::CheckoutSettings, &content)
     }
 
-    fn get_machine_file_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
-        // get app data dir
-        let Ok(data_dir) = app.path().app_data_dir() else {
-            return Err(Error::PathErr("Can't resolve app data dir".into()));
+    pub(crate) fn get_checkout_settings(
+        storage: &dyn KeygenStorage,
+    ) -> Result<Option<CheckoutSettings>> {
+        let Some(content) = storage.read(&StorageEntry::CheckoutSettings)? else {
+            return Ok(None);
         };
 
-        // get cache dir
-        let cache_dir = data_dir.join("keygen");
-
-        if !cache_dir.exists() {
-            fs::create_dir_all(&cache_dir)?;
+        match serde_json::from_str(&content) {
+            Ok(settings) => Ok(Some(settings)),
+            Err(err) => {
+                dbg!(err);
+                storage.quarantine(&StorageEntry::CheckoutSettings)?;
+                Ok(None)
+            }
         }
-
-        // get path
-        let path = cache_dir.join("machine.lic");
-
-        Ok(path)
     }
 }
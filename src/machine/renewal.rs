@@ -0,0 +1,284 @@
+use super::Machine;
+use crate::{
+    client::KeygenClient,
+    err::{Error, ErrorSummary},
+    events,
+    licensed::{license::License, LicensedState},
+    storage::Storage,
+    Result,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tokio::sync::Mutex;
+
+pub(crate) static RENEWAL_FAILED_EVENT: &str = "keygen://machine-file-renewal-failed";
+
+// how often to look at the machine file
+static CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// delay before retrying a failed (or offline) renewal
+static RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// consecutive failures before emitting RENEWAL_FAILED_EVENT
+static MAX_FAILURES: u32 = 3;
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct RenewalFailed {
+    failures: u32,
+    error: ErrorSummary,
+}
+
+enum Renewal {
+    NotDue,
+    Renewed,
+    Offline,
+}
+
+// renew the machine file in the background, once less than `threshold` of its ttl is left
+// (0 disables renewal), and follow the expiry of the license it was loaded from
+pub(crate) fn spawn<R: Runtime>(app: AppHandle<R>, threshold: f64) {
+    tauri::async_runtime::spawn(async move {
+        let mut failures = 0;
+        let mut reported_error: Option<String> = None;
+        let mut expiring_emitted = false;
+
+        loop {
+            let delay = match renew_if_due(&app, threshold).await {
+                Ok(Renewal::NotDue) | Ok(Renewal::Renewed) => {
+                    failures = 0;
+                    reported_error = None;
+                    CHECK_INTERVAL
+                }
+                // network unreachable: not a failure, try again later
+                Ok(Renewal::Offline) => RETRY_INTERVAL,
+                Err(err) => {
+                    dbg!(&err);
+                    failures += 1;
+
+                    let error: ErrorSummary = err.into();
+
+                    // once the threshold is crossed, then only when the failure changed
+                    if failures >= MAX_FAILURES && reported_error.as_ref() != Some(&error.code) {
+                        reported_error = Some(error.code.clone());
+
+                        let payload = RenewalFailed { failures, error };
+
+                        if let Err(err) = app.emit(RENEWAL_FAILED_EVENT, payload) {
+                            dbg!(err);
+                        }
+                    }
+
+                    RETRY_INTERVAL
+                }
+            };
+
+            // wake up when the machine file expires, not up to an interval later
+            let delay = match check_expiry(&app, threshold, &mut expiring_emitted).await {
+                Ok(Some(until_expiry)) => delay.min(until_expiry),
+                Ok(None) => delay,
+                Err(err) => {
+                    dbg!(err);
+                    delay
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
+
+async fn renew_if_due<R: Runtime>(app: &AppHandle<R>, threshold: f64) -> Result<Renewal> {
+    if threshold <= 0.0 {
+        return Ok(Renewal::NotDue);
+    }
+
+    let storage = app.state::<Storage>();
+    let machine = app.state::<Mutex<Machine>>();
+    let client = app.state::<Mutex<KeygenClient>>();
+    let licensed_state = app.state::<Mutex<LicensedState>>();
+
+    let machine = machine.lock().await;
+    let client = client.lock().await;
+
+    // no machine file checked out with this plugin
+    let Some(settings) = Machine::get_checkout_settings(storage.as_ref())? else {
+        return Ok(Renewal::NotDue);
+    };
+
+    let Some(key) = LicensedState::get_cached_license_key(storage.as_ref(), &machine)? else {
+        return Ok(Renewal::NotDue);
+    };
+
+    let Some(machine_license) = machine.load_machine_file(&key, &client, storage.as_ref())? else {
+        return Ok(Renewal::NotDue);
+    };
+
+    // checked out forever
+    let Some(expiry) = machine_license.meta.expiry else {
+        return Ok(Renewal::NotDue);
+    };
+
+    if !is_due(
+        &machine_license.meta.issued,
+        &expiry,
+        client.now(),
+        threshold,
+    )? {
+        return Ok(Renewal::NotDue);
+    }
+
+    let licensed_state = licensed_state.lock().await;
+
+    // nothing to renew with, e.g. after resetLicense()
+    if licensed_state.get_license().is_none() {
+        return Ok(Renewal::NotDue);
+    }
+
+    // same ttl settings as the original checkout
+    match machine
+        .checkout(
+            &licensed_state,
+            &client,
+            storage.as_ref(),
+            settings.ttl_seconds,
+            settings.ttl_forever,
+        )
+        .await
+    {
+        Ok(()) => Ok(Renewal::Renewed),
+        Err(Error::RequestError(err)) => {
+            dbg!(err);
+            events::offline(app, &licensed_state);
+            Ok(Renewal::Offline)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// reload the licensed state once the machine file it was loaded from expired,
+// and emit `expiring` once while the same license gets close to its expiry.
+// returns the time left until the machine file expires.
+async fn check_expiry<R: Runtime>(
+    app: &AppHandle<R>,
+    threshold: f64,
+    expiring_emitted: &mut bool,
+) -> Result<Option<Duration>> {
+    let storage = app.state::<Storage>();
+    let machine = app.state::<Mutex<Machine>>();
+    let client = app.state::<Mutex<KeygenClient>>();
+    let licensed_state = app.state::<Mutex<LicensedState>>();
+
+    let machine = machine.lock().await;
+    let client = client.lock().await;
+    let mut licensed_state = licensed_state.lock().await;
+
+    let now = client.now();
+    let mut machine_file_expiry = None;
+
+    if licensed_state.is_from_machine_file() {
+        let machine_license =
+            match LicensedState::get_cached_license_key(storage.as_ref(), &machine)? {
+                Some(key) => machine.load_machine_file(&key, &client, storage.as_ref())?,
+                None => None,
+            };
+
+        machine_file_expiry = machine_license
+            .as_ref()
+            .and_then(|machine_license| machine_license.meta.expiry.clone());
+
+        let expired = match machine_license {
+            Some(machine_license) => License::from_machine_license(machine_license, now)?.is_none(),
+            None => true,
+        };
+
+        if expired {
+            licensed_state.reload(app, storage.as_ref(), &client, &machine);
+
+            // update_license() already emitted `expiring` if needed
+            *expiring_emitted = licensed_state
+                .get_license()
+                .is_some_and(|license| events::is_expiring(&license, now));
+
+            return Ok(None);
+        }
+    }
+
+    let license = licensed_state.get_license();
+
+    // a machine file kept fresh by renewal doesn't make the license expiring
+    let expiring = license.as_ref().is_some_and(|license| {
+        events::is_expiring(license, now)
+            || (threshold <= 0.0
+                && license.valid
+                && machine_file_expiry
+                    .as_deref()
+                    .is_some_and(|expiry| events::expires_soon(expiry, now)))
+    });
+
+    if let Some(license) = license.filter(|_| expiring && !*expiring_emitted) {
+        events::expiring(app, &license);
+    }
+
+    *expiring_emitted = expiring;
+
+    Ok(machine_file_expiry
+        .and_then(|expiry| DateTime::parse_from_rfc3339(&expiry).ok())
+        .and_then(|expiry| expiry.signed_duration_since(now).to_std().ok()))
+}
+
+fn is_due(issued: &str, expiry: &str, now: DateTime<Utc>, threshold: f64) -> Result<bool> {
+    let issued = DateTime::parse_from_rfc3339(issued)
+        .map_err(|_| Error::ParseErr("Failed parsing machine file issued date".into()))?;
+    let expiry = DateTime::parse_from_rfc3339(expiry)
+        .map_err(|_| Error::ParseErr("Failed parsing machine file expiry date".into()))?;
+
+    let ttl = expiry.signed_duration_since(issued).num_seconds();
+    let remaining = expiry.signed_duration_since(now).num_seconds();
+
+    if ttl <= 0 {
+        return Ok(true);
+    }
+
+    Ok((remaining as f64 / ttl as f64) < threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_due;
+    use chrono::{DateTime, Utc};
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().into()
+    }
+
+    #[test]
+    fn is_due_once_below_threshold() {
+        let issued = "2024-01-01T00:00:00Z";
+        let expiry = "2024-01-11T00:00:00Z";
+
+        // 5 of 10 days left
+        assert!(!is_due(issued, expiry, at("2024-01-06T00:00:00Z"), 0.25).unwrap());
+        // 2 of 10 days left
+        assert!(is_due(issued, expiry, at("2024-01-09T00:00:00Z"), 0.25).unwrap());
+        // expired
+        assert!(is_due(issued, expiry, at("2024-01-12T00:00:00Z"), 0.25).unwrap());
+    }
+
+    #[test]
+    fn is_due_without_ttl() {
+        let date = "2024-01-01T00:00:00Z";
+
+        assert!(is_due(date, date, at(date), 0.25).unwrap());
+    }
+
+    #[test]
+    fn is_due_fails_on_invalid_dates() {
+        let now = at("2024-01-01T00:00:00Z");
+
+        assert!(is_due("not a date", "2024-01-11T00:00:00Z", now, 0.25).is_err());
+        assert!(is_due("2024-01-01T00:00:00Z", "not a date", now, 0.25).is_err());
+    }
+}
@@ -1,4 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+// the settings a machine file was checked out with, reused on renewal
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutSettings {
+    pub ttl_seconds: u32,
+    pub ttl_forever: bool,
+}
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct MachineFileRes {
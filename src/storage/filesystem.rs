@@ -0,0 +1,203 @@
+use super::{KeygenStorage, StorageEntry};
+use crate::Result;
+use chrono::Utc;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// default storage: files under `[APP_DATA]/keygen`,
+// and the activated fingerprint under `[APP_LOCAL_DATA]/keygen`
+#[derive(Debug, Clone)]
+pub struct FsStorage {
+    dir: PathBuf,
+    local_dir: Option<PathBuf>,
+}
+
+impl FsStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            local_dir: None,
+        }
+    }
+
+    // for entries that must not follow a roaming profile to another machine
+    pub fn local_dir(mut self, local_dir: impl Into<PathBuf>) -> Self {
+        self.local_dir = Some(local_dir.into());
+        self
+    }
+
+    fn get_path(&self, entry: &StorageEntry) -> PathBuf {
+        match entry {
+            StorageEntry::LicenseKey => self.dir.join("key"),
+            StorageEntry::MachineFile => self.dir.join("machine.lic"),
+            StorageEntry::LicenseFile => self.dir.join("license.lic"),
+            StorageEntry::CheckoutSettings => self.dir.join("checkout.json"),
+            StorageEntry::LastValidation => self.dir.join("last_validation.json"),
+            StorageEntry::InstallId => self.dir.join("install_id"),
+            StorageEntry::ActivatedFingerprint => self
+                .local_dir
+                .as_ref()
+                .unwrap_or(&self.dir)
+                .join("fingerprint"),
+            StorageEntry::ClockSkew => self.dir.join("clock_skew"),
+            StorageEntry::LastSeen => self.dir.join("last_seen"),
+            StorageEntry::ReportedVersion => self.dir.join("reported_version"),
+            StorageEntry::ValidationCache(name) => self.get_validation_cache_dir().join(name),
+        }
+    }
+
+    fn get_validation_cache_dir(&self) -> PathBuf {
+        self.dir.join("validation_cache")
+    }
+
+    fn get_quarantine_dir(&self) -> PathBuf {
+        self.dir.join("quarantine")
+    }
+
+    // advisory lock on the keygen dir, shared with other app instances
+    fn with_lock<T>(&self, exclusive: bool, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if !self.dir.exists() {
+            fs::create_dir_all(&self.dir)?;
+        }
+
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(".lock"))?;
+
+        let mut lock = fd_lock::RwLock::new(lock_file);
+
+        if exclusive {
+            let _guard = lock.write()?;
+            f()
+        } else {
+            let _guard = lock.read()?;
+            f()
+        }
+    }
+
+    // write to a temp file, then rename it over the target.
+    // a crash can't leave a truncated file behind.
+    fn write_atomic(path: &Path, content: &str) -> Result<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        if !dir.exists() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = dir.join(format!(".{}.tmp", file_name));
+
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(content.as_bytes())?;
+        f.sync_all()?;
+
+        fs::rename(&tmp_path, path)?;
+
+        // persist the rename itself
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+impl KeygenStorage for FsStorage {
+    fn read(&self, entry: &StorageEntry) -> Result<Option<String>> {
+        let path = self.get_path(entry);
+
+        self.with_lock(false, || {
+            if !path.exists() {
+                return Ok(None);
+            }
+
+            Ok(Some(fs::read_to_string(&path)?))
+        })
+    }
+
+    fn write(&self, entry: &StorageEntry, content: &str) -> Result<()> {
+        let path = self.get_path(entry);
+
+        self.with_lock(true, || Self::write_atomic(&path, content))
+    }
+
+    fn remove(&self, entry: &StorageEntry) -> Result<()> {
+        let path = self.get_path(entry);
+
+        self.with_lock(true, || {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn quarantine(&self, entry: &StorageEntry) -> Result<()> {
+        let path = self.get_path(entry);
+        let quarantine_dir = self.get_quarantine_dir();
+
+        self.with_lock(true, || {
+            if !path.exists() {
+                return Ok(());
+            }
+
+            if !quarantine_dir.exists() {
+                fs::create_dir_all(&quarantine_dir)?;
+            }
+
+            // keep it around for inspection, e.g. 'machine.lic.1718000000'
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let quarantined =
+                quarantine_dir.join(format!("{}.{}", file_name, Utc::now().timestamp()));
+
+            fs::rename(&path, quarantined)?;
+
+            Ok(())
+        })
+    }
+
+    fn list_validation_cache(&self) -> Result<Vec<String>> {
+        let dir = self.get_validation_cache_dir();
+
+        self.with_lock(false, || {
+            let mut names = vec![];
+
+            if dir.exists() && dir.is_dir() {
+                for entry in fs::read_dir(&dir)? {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+
+                    // skip temp files
+                    if entry.path().is_file() && !name.starts_with('.') {
+                        names.push(name);
+                    }
+                }
+            }
+
+            Ok(names)
+        })
+    }
+
+    fn clear_validation_cache(&self) -> Result<()> {
+        let dir = self.get_validation_cache_dir();
+
+        self.with_lock(true, || {
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,51 @@
+use super::{KeygenStorage, StorageEntry};
+use crate::Result;
+use std::{collections::HashMap, sync::Mutex};
+
+// nothing is persisted: useful for tests, or apps that always validate online
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<HashMap<StorageEntry, String>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<StorageEntry, String>> {
+        // a panic while holding the lock can't leave a half written entry
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl KeygenStorage for MemoryStorage {
+    fn read(&self, entry: &StorageEntry) -> Result<Option<String>> {
+        Ok(self.entries().get(entry).cloned())
+    }
+
+    fn write(&self, entry: &StorageEntry, content: &str) -> Result<()> {
+        self.entries().insert(entry.clone(), content.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, entry: &StorageEntry) -> Result<()> {
+        self.entries().remove(entry);
+        Ok(())
+    }
+
+    fn list_validation_cache(&self) -> Result<Vec<String>> {
+        let names = self
+            .entries()
+            .keys()
+            .filter_map(|entry| match entry {
+                StorageEntry::ValidationCache(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Ok(names)
+    }
+}
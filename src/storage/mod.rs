@@ -0,0 +1,100 @@
+mod filesystem;
+mod memory;
+#[cfg(feature = "store")]
+mod store;
+
+pub use filesystem::FsStorage;
+pub use memory::MemoryStorage;
+#[cfg(feature = "store")]
+pub use store::StoreStorage;
+
+use crate::Result;
+use std::sync::Arc;
+
+pub(crate) type Storage = Arc<dyn KeygenStorage>;
+
+// usernames may contain characters that aren't valid in paths
+pub(crate) fn user_dir_name(user: &str) -> String {
+    let name: String = user
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    // no '.', '..' or empty dir names
+    if name.chars().all(|c| c == '.') {
+        return format!("_{}", name);
+    }
+
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StorageEntry {
+    LicenseKey,
+    MachineFile,
+    LicenseFile,
+    CheckoutSettings,
+    LastValidation,
+    InstallId,
+    ActivatedFingerprint,
+    ClockSkew,
+    LastSeen,
+    ReportedVersion,
+    ValidationCache(String), // cache name
+}
+
+// where the plugin keeps its license state
+pub trait KeygenStorage: Send + Sync {
+    fn read(&self, entry: &StorageEntry) -> Result<Option<String>>;
+
+    fn write(&self, entry: &StorageEntry, content: &str) -> Result<()>;
+
+    // removing a missing entry is not an error
+    fn remove(&self, entry: &StorageEntry) -> Result<()>;
+
+    // move a corrupt entry out of the way
+    fn quarantine(&self, entry: &StorageEntry) -> Result<()> {
+        self.remove(entry)
+    }
+
+    // names of all cached validation responses
+    fn list_validation_cache(&self) -> Result<Vec<String>>;
+
+    fn clear_validation_cache(&self) -> Result<()> {
+        for name in self.list_validation_cache()? {
+            self.remove(&StorageEntry::ValidationCache(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::user_dir_name;
+
+    #[test]
+    fn user_dir_name_keeps_safe_characters() {
+        assert_eq!(user_dir_name("alice.smith-01_x"), "alice.smith-01_x");
+    }
+
+    #[test]
+    fn user_dir_name_replaces_path_characters() {
+        assert_eq!(user_dir_name("CORP\\alice"), "CORP_alice");
+        assert_eq!(user_dir_name("../alice"), ".._alice");
+        assert_eq!(user_dir_name("José"), "Jos_");
+    }
+
+    #[test]
+    fn user_dir_name_avoids_dot_names() {
+        assert_eq!(user_dir_name(""), "_");
+        assert_eq!(user_dir_name("."), "_.");
+        assert_eq!(user_dir_name(".."), "_..");
+    }
+}
@@ -0,0 +1,75 @@
+use super::{KeygenStorage, StorageEntry};
+use crate::{err::Error, Result};
+use std::sync::Arc;
+use tauri::Runtime;
+use tauri_plugin_store::Store;
+
+static VALIDATION_CACHE_PREFIX: &str = "keygen/validation_cache/";
+
+// keeps license state in a `tauri-plugin-store` store,
+// e.g. one that's backed by the app's own encrypted vault
+pub struct StoreStorage<R: Runtime> {
+    store: Arc<Store<R>>,
+}
+
+impl<R: Runtime> StoreStorage<R> {
+    pub fn new(store: Arc<Store<R>>) -> Self {
+        Self { store }
+    }
+
+    fn get_key(entry: &StorageEntry) -> String {
+        match entry {
+            StorageEntry::LicenseKey => "keygen/key".into(),
+            StorageEntry::MachineFile => "keygen/machine.lic".into(),
+            StorageEntry::LicenseFile => "keygen/license.lic".into(),
+            StorageEntry::CheckoutSettings => "keygen/checkout.json".into(),
+            StorageEntry::LastValidation => "keygen/last_validation.json".into(),
+            StorageEntry::InstallId => "keygen/install_id".into(),
+            StorageEntry::ActivatedFingerprint => "keygen/fingerprint".into(),
+            StorageEntry::ClockSkew => "keygen/clock_skew".into(),
+            StorageEntry::LastSeen => "keygen/last_seen".into(),
+            StorageEntry::ReportedVersion => "keygen/reported_version".into(),
+            StorageEntry::ValidationCache(name) => format!("{}{}", VALIDATION_CACHE_PREFIX, name),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        self.store
+            .save()
+            .map_err(|err| Error::StorageErr(format!("Failed saving store: {}", err)))
+    }
+}
+
+impl<R: Runtime> KeygenStorage for StoreStorage<R> {
+    fn read(&self, entry: &StorageEntry) -> Result<Option<String>> {
+        match self.store.get(Self::get_key(entry)) {
+            Some(serde_json::Value::String(content)) => Ok(Some(content)),
+            Some(_) => Err(Error::StorageErr("Unexpected store value".into())),
+            None => Ok(None),
+        }
+    }
+
+    fn write(&self, entry: &StorageEntry, content: &str) -> Result<()> {
+        self.store.set(Self::get_key(entry), content);
+        self.save()
+    }
+
+    fn remove(&self, entry: &StorageEntry) -> Result<()> {
+        if self.store.delete(Self::get_key(entry)) {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn list_validation_cache(&self) -> Result<Vec<String>> {
+        let names = self
+            .store
+            .keys()
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(VALIDATION_CACHE_PREFIX).map(Into::into))
+            .collect();
+
+        Ok(names)
+    }
+}